@@ -1,16 +1,68 @@
 /*!
     Contains items related to program args.
 */
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Debug, Clone, PartialEq, Parser)]
+#[clap(args_conflicts_with_subcommands = true)]
 pub struct Args {
     /// Enables or disables ast output.
     #[clap(short, long)]
     pub show_ast: bool,
 
-    /// File to parse.
-    pub file: String
+    /// Denies running programs, file access, environment variables and standard input, unless allowed by other flags.
+    #[clap(long)]
+    pub sandbox: bool,
+
+    /// Only allows running these programs, given as a comma-separated list. Shell code needs `sh` to be allowed.
+    #[clap(long, value_delimiter = ',', value_name = "PROGRAMS")]
+    pub allow_exec: Option<Vec<String>>,
+
+    /// Allows reading files in sandbox mode.
+    #[clap(long)]
+    pub allow_read: bool,
+
+    /// Allows writing files in sandbox mode.
+    #[clap(long)]
+    pub allow_write: bool,
+
+    /// Allows accessing environment variables in sandbox mode.
+    #[clap(long)]
+    pub allow_env: bool,
+
+    /// Allows reading standard input in sandbox mode.
+    #[clap(long)]
+    pub allow_stdin: bool,
+
+    /// Stops code with an error after it evaluates this many statements and expressions.
+    #[clap(long, value_name = "STEPS")]
+    pub max_steps: Option<u64>,
+
+    /// Stops code with an error once function calls are nested deeper than this. Defaults to 128.
+    #[clap(long, value_name = "DEPTH")]
+    pub max_call_depth: Option<usize>,
+
+    /// Stops code with an error after it runs for this many seconds.
+    #[clap(long, value_name = "SECONDS")]
+    pub timeout: Option<f64>,
+
+    /// Stops code with an error once a list or map holds more than this many items.
+    #[clap(long, value_name = "ITEMS")]
+    pub max_collection_len: Option<usize>,
+
+    /// File to parse. A REPL is started if no file is given.
+    pub file: Option<String>,
+
+    /// Command to run instead of parsing a file.
+    #[clap(subcommand)]
+    pub command: Option<Command>
+}
+
+/// A command to run instead of parsing a file.
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Starts a REPL that evaluates code as it is typed.
+    Repl
 }
 
 /// Returns parsed args.
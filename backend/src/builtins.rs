@@ -0,0 +1,234 @@
+/*!
+    Contains the functions that are built into the language.
+*/
+
+use std::{cell::RefCell, path::PathBuf, rc::Rc, time::Duration};
+use anyhow::anyhow;
+use crate::{
+    permissions::{Capability, Permissions, SHELL_PROGRAM},
+    runtime::{RuntimeMap, RuntimeVal},
+    shell::{run_pipeline, run_program, run_shell, CommandOutput, Program, Redirect, RunOptions}
+};
+
+/// The names of every builtin function.
+const BUILTINS: &[&str] = &["sh", "exec", "pipe", "read_file", "write_file", "env_var"];
+
+/// Returns true if there is a builtin function named `name`.
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name)
+}
+
+/// Calls the builtin function named `name` with already evaluated `args`, failing if it does something `permissions` deny.
+///
+/// Commands are stopped once they run for longer than `time_left`, if given.
+pub fn call_builtin(
+    name: &str,
+    args: Vec<RuntimeVal>,
+    permissions: &Permissions,
+    time_left: Option<Duration>
+) -> anyhow::Result<RuntimeVal> {
+    match name {
+        "sh" => sh(args, permissions, time_left),
+        "exec" => exec(args, permissions, time_left),
+        "pipe" => pipe(args, permissions, time_left),
+        "read_file" => read_file(args, permissions),
+        "write_file" => write_file(args, permissions),
+        "env_var" => env_var(args, permissions),
+        _ => Err(anyhow!("undefined function `{name}`"))
+    }
+}
+
+/// Options for running a command, read from a map.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct CommandOptions {
+    /// Makes a non-zero exit code an error.
+    check: bool,
+    run: RunOptions
+}
+
+impl CommandOptions {
+    /// Reads options passed to the builtin function named `name`, if any were passed, failing if `permissions` deny them.
+    ///
+    /// The command can't run for longer than `time_left`, even if a longer `timeout` option is passed.
+    fn read(
+        name: &str,
+        options: Option<&Rc<RefCell<RuntimeMap>>>,
+        permissions: &Permissions,
+        time_left: Option<Duration>
+    ) -> anyhow::Result<Self> {
+        let mut read = Self { run: RunOptions { timeout: time_left, ..RunOptions::default() }, ..Self::default() };
+        let Some(options) = options else {
+            return Ok(read);
+        };
+
+        let mut append = false;
+        for (key, value) in options.borrow().iter() {
+            match (key.as_str(), value) {
+                ("check", RuntimeVal::Bool(b)) => read.check = *b,
+                ("append", RuntimeVal::Bool(b)) => append = *b,
+                ("stdin", RuntimeVal::Str(s)) => read.run.stdin = Some(s.clone()),
+                ("cwd", RuntimeVal::Str(s)) => read.run.cwd = Some(PathBuf::from(s)),
+                ("stdout", RuntimeVal::Str(s)) => read.run.stdout = Redirect::File(PathBuf::from(s)),
+                ("stderr", RuntimeVal::Str(s)) => read.run.stderr = Redirect::File(PathBuf::from(s)),
+                ("timeout", RuntimeVal::Num(n)) if *n >= 0.0 && n.is_finite() => {
+                    let timeout = Duration::from_secs_f64(*n);
+                    read.run.timeout = Some(time_left.map_or(timeout, |left| left.min(timeout)));
+                }
+                ("env", RuntimeVal::Map(vars)) => {
+                    for (var, value) in vars.borrow().iter() {
+                        let value = arg_to_string(value.clone())
+                            .map_err(|_| anyhow!("{name} option 'env' must hold strings, numbers or bools, but got {value}"))?;
+                        read.run.env.push((var.clone(), value));
+                    }
+                }
+                ("check" | "append", value) => return Err(anyhow!("{name} option '{key}' must be a bool, but got {value}")),
+                ("stdin" | "cwd" | "stdout" | "stderr", value) => return Err(anyhow!("{name} option '{key}' must be a string, but got {value}")),
+                ("timeout", value) => return Err(anyhow!("{name} option 'timeout' must be a number of seconds, but got {value}")),
+                ("env", value) => return Err(anyhow!("{name} option 'env' must be a map, but got {value}")),
+                _ => return Err(anyhow!("unknown {name} option '{key}'"))
+            }
+        }
+
+        // Setting variables such as `PATH` can change which program runs, so it needs more than exec access
+        if !read.run.env.is_empty() {
+            permissions.check(Capability::Env)?;
+        }
+        if read.run.stdout != Redirect::Capture || read.run.stderr != Redirect::Capture {
+            permissions.check(Capability::FileWrite)?;
+        }
+
+        // Output files are only added to if asked to, which can come before or after them in the map
+        if append {
+            for redirect in [&mut read.run.stdout, &mut read.run.stderr] {
+                if let Redirect::File(path) = redirect {
+                    *redirect = Redirect::Append(path.clone());
+                }
+            }
+        }
+
+        Ok(read)
+    }
+
+    /// Returns the output of a finished command as a map, or an error if it failed and should be checked.
+    ///
+    /// `command` is how the command is shown in errors.
+    fn finish(&self, output: CommandOutput, command: &str) -> anyhow::Result<RuntimeVal> {
+        if self.check && !output.success() {
+            return Err(output.failure_error(command));
+        }
+
+        Ok(output.to_runtime_val())
+    }
+}
+
+/// Runs a shell command, returning a map of its `stdout`, `stderr` and exit `code`.
+///
+/// Takes an optional map of options, such as `check`, which makes a non-zero exit code an error.
+fn sh(args: Vec<RuntimeVal>, permissions: &Permissions, time_left: Option<Duration>) -> anyhow::Result<RuntimeVal> {
+    let (command, options) = match args.as_slice() {
+        [RuntimeVal::Str(command)] => (command, None),
+        [RuntimeVal::Str(command), RuntimeVal::Map(options)] => (command, Some(options)),
+        _ => return Err(anyhow!("sh takes a command string and an optional map of options"))
+    };
+
+    permissions.check_exec(SHELL_PROGRAM)?;
+    let options = CommandOptions::read("sh", options, permissions, time_left)?;
+    options.finish(run_shell(command, &options.run)?, command)
+}
+
+/// Runs a program directly with a list of args, without a shell, returning the same map as `sh`.
+///
+/// Takes the same options as `sh`.
+fn exec(args: Vec<RuntimeVal>, permissions: &Permissions, time_left: Option<Duration>) -> anyhow::Result<RuntimeVal> {
+    let (program, program_args, options) = match args.as_slice() {
+        [RuntimeVal::Str(program)] => (program, None, None),
+        [RuntimeVal::Str(program), RuntimeVal::List(args)] => (program, Some(args), None),
+        [RuntimeVal::Str(program), RuntimeVal::List(args), RuntimeVal::Map(options)] => (program, Some(args), Some(options)),
+        _ => return Err(anyhow!("exec takes a program name, an optional list of args and an optional map of options"))
+    };
+
+    // Args reach the program as they are, so they need no quoting
+    let program_args = program_args
+        .map(|args| args.borrow().iter().cloned().map(arg_to_string).collect::<anyhow::Result<Vec<_>>>())
+        .transpose()?
+        .unwrap_or_default();
+
+    permissions.check_exec(program)?;
+    let options = CommandOptions::read("exec", options, permissions, time_left)?;
+    options.finish(run_program(program, &program_args, &options.run)?, program)
+}
+
+/// Runs a list of programs, each given as a list of a name followed by args, with the output of each one going to the next one.
+///
+/// Returns the same map as `sh`, with the exit code of the last program, and takes the same options.
+fn pipe(args: Vec<RuntimeVal>, permissions: &Permissions, time_left: Option<Duration>) -> anyhow::Result<RuntimeVal> {
+    let (stages, options) = match args.as_slice() {
+        [RuntimeVal::List(stages)] => (stages, None),
+        [RuntimeVal::List(stages), RuntimeVal::Map(options)] => (stages, Some(options)),
+        _ => return Err(anyhow!("pipe takes a list of programs and an optional map of options"))
+    };
+
+    let mut programs = Vec::new();
+    for stage in stages.borrow().iter() {
+        let RuntimeVal::List(stage) = stage else {
+            return Err(anyhow!("pipe programs must be lists of a name followed by args, but got {stage}"));
+        };
+        let mut words = stage.borrow().iter().cloned().map(arg_to_string).collect::<anyhow::Result<Vec<_>>>()?;
+        if words.is_empty() {
+            return Err(anyhow!("pipe programs must have a name"));
+        }
+        let name = words.remove(0);
+        permissions.check_exec(&name)?;
+        programs.push(Program::new(name, words));
+    }
+
+    // Show the pipeline the way a shell would write it
+    let command = programs.iter()
+        .map(|p| std::iter::once(&p.name).chain(&p.args).map(String::as_str).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join(" | ");
+
+    let options = CommandOptions::read("pipe", options, permissions, time_left)?;
+    options.finish(run_pipeline(&programs, &options.run)?, &command)
+}
+
+/// Returns the contents of a file.
+fn read_file(args: Vec<RuntimeVal>, permissions: &Permissions) -> anyhow::Result<RuntimeVal> {
+    let [RuntimeVal::Str(path)] = args.as_slice() else {
+        return Err(anyhow!("read_file takes a file path"));
+    };
+    permissions.check(Capability::FileRead)?;
+
+    std::fs::read_to_string(path)
+        .map(RuntimeVal::Str)
+        .map_err(|e| anyhow!("couldn't read file '{path}': {e}"))
+}
+
+/// Replaces the contents of a file with a string, creating it if needed.
+fn write_file(args: Vec<RuntimeVal>, permissions: &Permissions) -> anyhow::Result<RuntimeVal> {
+    let [RuntimeVal::Str(path), RuntimeVal::Str(contents)] = args.as_slice() else {
+        return Err(anyhow!("write_file takes a file path and a string to write"));
+    };
+    permissions.check(Capability::FileWrite)?;
+
+    std::fs::write(path, contents).map_err(|e| anyhow!("couldn't write file '{path}': {e}"))?;
+    Ok(RuntimeVal::Null)
+}
+
+/// Returns the value of an environment variable, or null if it isn't set.
+fn env_var(args: Vec<RuntimeVal>, permissions: &Permissions) -> anyhow::Result<RuntimeVal> {
+    let [RuntimeVal::Str(name)] = args.as_slice() else {
+        return Err(anyhow!("env_var takes a variable name"));
+    };
+    permissions.check(Capability::Env)?;
+
+    Ok(std::env::var(name).map(RuntimeVal::Str).unwrap_or(RuntimeVal::Null))
+}
+
+/// Converts a value to a command arg, failing if it isn't a string, number or bool.
+pub fn arg_to_string(val: RuntimeVal) -> anyhow::Result<String> {
+    match val {
+        RuntimeVal::Str(_) | RuntimeVal::Num(_) | RuntimeVal::Bool(_) => Ok(val.to_string()),
+        val => Err(anyhow!("command args must be strings, numbers or bools, but got {val}"))
+    }
+}
@@ -0,0 +1,86 @@
+/*!
+    Contains the console that `print` writes to and `read` reads from.
+*/
+
+use std::{cell::RefCell, fmt, io::{self, BufRead, Write}};
+
+/// Where `print` writes its output and `read` gets its input from.
+pub trait Console: fmt::Debug {
+    /// Writes `line` followed by a line ending.
+    fn write_line(&self, line: &str) -> anyhow::Result<()>;
+
+    /// Reads the next line of input along with its line ending, or an empty string once input ends.
+    fn read_line(&self) -> anyhow::Result<String>;
+}
+
+/// A console that uses standard output and standard input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StdConsole;
+
+impl Console for StdConsole {
+    fn write_line(&self, line: &str) -> anyhow::Result<()> {
+        writeln!(io::stdout().lock(), "{line}")?;
+        Ok(())
+    }
+
+    fn read_line(&self) -> anyhow::Result<String> {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        Ok(line)
+    }
+}
+
+/// A console that reads from a string given up front and keeps everything written to it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemoryConsole {
+    /// The input that hasn't been read yet.
+    input: RefCell<String>,
+    /// Everything written so far.
+    output: RefCell<String>
+}
+
+impl MemoryConsole {
+    /// Constructs a new console that reads `input`.
+    pub fn new(input: &str) -> Self {
+        Self { input: RefCell::new(input.to_string()), output: RefCell::default() }
+    }
+
+    /// Returns everything written so far.
+    pub fn output(&self) -> String {
+        self.output.borrow().clone()
+    }
+}
+
+impl Console for MemoryConsole {
+    fn write_line(&self, line: &str) -> anyhow::Result<()> {
+        let mut output = self.output.borrow_mut();
+        output.push_str(line);
+        output.push('\n');
+        Ok(())
+    }
+
+    fn read_line(&self) -> anyhow::Result<String> {
+        let mut input = self.input.borrow_mut();
+        let end = input.find('\n').map_or(input.len(), |i| i + 1);
+        Ok(input.drain(..end).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+
+    #[test]
+    fn test_memory_console() {
+        let console = MemoryConsole::new("first\nsecond");
+
+        assert_eq!(console.read_line().unwrap(), "first\n");
+        assert_eq!(console.read_line().unwrap(), "second");
+        assert_eq!(console.read_line().unwrap(), "");
+
+        console.write_line("a").unwrap();
+        console.write_line("b").unwrap();
+        assert_eq!(console.output(), "a\nb\n");
+    }
+}
@@ -0,0 +1,179 @@
+/*!
+    Contains the engine, which runs code from Rust against an environment that is kept between runs.
+*/
+
+use std::rc::Rc;
+use anyhow::anyhow;
+use frontend::parser::parse_program;
+use crate::{
+    console::{Console, StdConsole},
+    limits::Limits,
+    permissions::Permissions,
+    runtime::{FromRuntimeVal, NativeFn, RuntimeEnv, RuntimeVal},
+    visitor::GeneralVisitor
+};
+
+/// Runs code against global variables that are kept between runs, so values can be passed in and read back out.
+#[derive(Clone, Debug)]
+pub struct Engine {
+    /// The global environment every run is evaluated in.
+    env: Rc<RuntimeEnv>,
+    /// What code is allowed to do outside of the interpreter.
+    permissions: Permissions,
+    /// How much work each run is allowed to do.
+    limits: Limits,
+    /// Where `print` writes to and `read` reads from.
+    console: Rc<dyn Console>
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self {
+            env: Rc::default(),
+            permissions: Permissions::default(),
+            limits: Limits::default(),
+            console: Rc::new(StdConsole)
+        }
+    }
+}
+
+impl Engine {
+    /// Constructs a new engine with no variables.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns this engine with code only allowed to do what `permissions` allow.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Returns this engine with each run only allowed to do as much work as `limits` allow.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns this engine with `print` and `read` using `console`.
+    pub fn with_console(mut self, console: Rc<dyn Console>) -> Self {
+        self.console = console;
+        self
+    }
+
+    /// Returns the global environment code is run in.
+    pub fn env(&self) -> &Rc<RuntimeEnv> {
+        &self.env
+    }
+
+    /// Drops every variable, including registered functions.
+    pub fn reset(&mut self) {
+        self.env = Rc::default();
+    }
+
+    /// Declares a global variable, replacing any variable with the same name.
+    pub fn set_var(&self, name: &str, value: impl Into<RuntimeVal>) -> anyhow::Result<()> {
+        self.env.declare_var(name, value.into())
+    }
+
+    /// Returns the value of a global variable as a Rust value, failing if it isn't declared or has a different type.
+    pub fn get_var<T: FromRuntimeVal>(&self, name: &str) -> anyhow::Result<T> {
+        let value = self.env.get_var(name).ok_or_else(|| anyhow!("undefined variable `{name}`"))?;
+        T::from_runtime_val(value).map_err(|e| anyhow!("couldn't read variable `{name}`: {e}"))
+    }
+
+    /// Declares a global function implemented in Rust, which takes already evaluated args.
+    pub fn register_fn(
+        &self,
+        name: &str,
+        func: impl Fn(Vec<RuntimeVal>) -> anyhow::Result<RuntimeVal> + 'static
+    ) -> anyhow::Result<()> {
+        self.set_var(name, NativeFn::new(name, func))
+    }
+
+    /// Runs `code`, keeping every global variable it declares.
+    pub fn run(&self, code: &str) -> anyhow::Result<()> {
+        self.eval(code).map(|_| ())
+    }
+
+    /// Runs `code`, returning the value of its last expression statement, or null if it has none.
+    pub fn eval(&self, code: &str) -> anyhow::Result<RuntimeVal> {
+        let ast = parse_program(code)?;
+
+        GeneralVisitor::new(self.env.clone())
+            .with_permissions(self.permissions.clone())
+            .with_limits(self.limits.clone())
+            .with_console(self.console.clone())
+            .eval_program(&ast)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+    use std::{cell::RefCell, collections::HashMap};
+    use frontend::diagnostics::{Diagnostic, Span};
+
+    #[test]
+    fn test_engine_vars() {
+        let engine = Engine::new();
+        engine.set_var("name", "seel").unwrap();
+        engine.set_var("scores", vec![1, 2, 3]).unwrap();
+        engine.set_var("missing", None::<f64>).unwrap();
+
+        engine.run("let total = 0; for s in scores { total = total + s; }").unwrap();
+        engine.run(r#"let info = {"greeting": "hi {name}", "count": 3};"#).unwrap();
+
+        assert_eq!(engine.get_var::<f64>("total").unwrap(), 6.0);
+        assert_eq!(engine.get_var::<i64>("total").unwrap(), 6);
+        assert_eq!(engine.get_var::<Vec<i64>>("scores").unwrap(), vec![1, 2, 3]);
+        assert_eq!(engine.get_var::<Option<String>>("missing").unwrap(), None);
+
+        let info = engine.get_var::<HashMap<String, RuntimeVal>>("info").unwrap();
+        assert_eq!(info["greeting"], RuntimeVal::Str("hi seel".to_string()));
+        assert_eq!(info["count"], RuntimeVal::Num(3.0));
+
+        assert_eq!(engine.get_var::<String>("total").unwrap_err().to_string(), "couldn't read variable `total`: expected a string, but got 6");
+        assert_eq!(engine.get_var::<f64>("nope").unwrap_err().to_string(), "undefined variable `nope`");
+    }
+
+    #[test]
+    fn test_engine_native_fns() {
+        let engine = Engine::new();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+
+        let log = calls.clone();
+        engine.register_fn("record", move |args| {
+            log.borrow_mut().extend(args);
+            Ok(RuntimeVal::Null)
+        }).unwrap();
+        engine.register_fn("add", |args| match args.as_slice() {
+            [RuntimeVal::Num(a), RuntimeVal::Num(b)] => Ok(RuntimeVal::Num(a + b)),
+            _ => Err(anyhow!("add takes two numbers"))
+        }).unwrap();
+
+        assert_eq!(engine.eval("record(\"a\", 1); add(2, 3)").unwrap(), RuntimeVal::Num(5.0));
+        assert_eq!(*calls.borrow(), vec![RuntimeVal::Str("a".to_string()), RuntimeVal::Num(1.0)]);
+        assert_eq!(engine.eval("add").unwrap().to_string(), "<fn add>");
+
+        // Errors from native functions say where they were called
+        let error = engine.run("let x = add(1, true);").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.message, "add takes two numbers");
+        assert_eq!(diagnostic.span, Span::new(8, 20));
+    }
+
+    #[test]
+    fn test_engine_keeps_env() {
+        let mut engine = Engine::new();
+        engine.run("fn double(n) { return n * 2; }").unwrap();
+        engine.run("let x = double(21);").unwrap();
+
+        assert_eq!(engine.eval("x").unwrap(), RuntimeVal::Num(42.0));
+        assert_eq!(engine.eval("let y = 1;").unwrap(), RuntimeVal::Null);
+
+        engine.reset();
+        assert!(engine.eval("x").is_err());
+    }
+}
@@ -2,50 +2,145 @@
     Contains things related to the interpreter.
 */
 
-use frontend::{grammar, parser::{*}};
-use frontend::helper::convert_string_to_static_str;
-use crate::{runtime::RuntimeEnv, visitor::{GeneralVisitor, Visitor}};
+use std::{collections::HashMap, rc::Rc};
+use anyhow::anyhow;
+use frontend::{diagnostics::{Diagnostic, Diagnostics}, parser::{*}};
+use crate::{
+    console::{Console, StdConsole},
+    limits::Limits,
+    permissions::Permissions,
+    runtime::{FromRuntimeVal, RuntimeEnv, RuntimeVal},
+    visitor::GeneralVisitor
+};
 
 /// The interpreter that evaluates ast.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct Interpreter {
     /// The ast to evaluate.
     pub ast: Vec<Stmt>,
+    /// What the ast is allowed to do outside of the interpreter.
+    pub permissions: Permissions,
+    /// How much work the ast is allowed to do.
+    pub limits: Limits,
+    /// Where `print` writes to and `read` reads from.
+    pub console: Rc<dyn Console>
 }
 
 impl Interpreter {
-    /// Constructs a new interpreter with ast
+    /// Constructs a new interpreter with ast, allowing it to do anything.
     pub fn new(ast: Vec<Stmt>) -> Self {
-        Self { ast }
+        Self { ast, permissions: Permissions::default(), limits: Limits::default(), console: Rc::new(StdConsole) }
+    }
+
+    /// Returns this interpreter with the ast only allowed to do what `permissions` allow.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Returns this interpreter with the ast only allowed to do as much work as `limits` allow.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns this interpreter with `print` and `read` using `console`.
+    pub fn with_console(mut self, console: Rc<dyn Console>) -> Self {
+        self.console = console;
+        self
     }
 
     /// Runs the interpreter, evaluating the ast.
     pub fn run(&self) -> anyhow::Result<()> {
+        self.eval().map(|_| ())
+    }
+
+    /// Runs the interpreter, returning the value of the last expression statement and the global variables it left behind.
+    pub fn eval(&self) -> anyhow::Result<RunOutput> {
         // Create runtime environment for general visitor
-        let runtime_env = RuntimeEnv::default();
+        let runtime_env = Rc::new(RuntimeEnv::default());
 
         // Run general visitor to evaluate everything
-        _ = GeneralVisitor::new(Box::new(runtime_env)).visit_program(&self.ast)?;
+        let value = GeneralVisitor::new(runtime_env.clone())
+            .with_permissions(self.permissions.clone())
+            .with_limits(self.limits.clone())
+            .with_console(self.console.clone())
+            .eval_program(&self.ast)?;
+
+        let globals = runtime_env.symbols.borrow().clone();
+        Ok(RunOutput { value, globals })
+    }
+}
 
-        // Return no errors
-        Ok(())
+/// What running code evaluated to and left behind.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunOutput {
+    /// The value of the last expression statement evaluated, or null if there wasn't one.
+    pub value: RuntimeVal,
+    /// Every global variable once the code finished running, by name.
+    pub globals: HashMap<String, RuntimeVal>
+}
+
+impl RunOutput {
+    /// Returns the value of a global variable as a Rust value, failing if it isn't declared or has a different type.
+    pub fn get_var<T: FromRuntimeVal>(&self, name: &str) -> anyhow::Result<T> {
+        let value = self.globals.get(name).cloned().ok_or_else(|| anyhow!("undefined variable `{name}`"))?;
+        T::from_runtime_val(value).map_err(|e| anyhow!("couldn't read variable `{name}`: {e}"))
     }
 }
 
 /// Takes code, parses that code to an ast, and runs the ast.
 pub fn run_code(code: &str) -> anyhow::Result<()> {
-    _ = Interpreter::new(grammar::ProgramParser::new().parse(
-        unsafe { convert_string_to_static_str(filter_comments(code).to_string()) }
-    )?).run()?;
+    eval_code(code).map(|_| ())
+}
+
+/// Takes code, parses that code to an ast, and runs the ast, returning what it evaluated to and left behind.
+pub fn eval_code(code: &str) -> anyhow::Result<RunOutput> {
+    Interpreter::new(parse_program(code)?).eval()
+}
 
-    // Return no errors
-    Ok(())
+/// Renders an error returned by running `code`, along with where it was found if known.
+/// 
+/// `name` is the name of the file `code` was read from.
+pub fn render_error(error: &anyhow::Error, name: &str, code: &str) -> String {
+    if let Some(diagnostics) = error.downcast_ref::<Diagnostics>() {
+        diagnostics.render(name, code)
+    } else if let Some(diagnostic) = error.downcast_ref::<Diagnostic>() {
+        diagnostic.render(name, code)
+    } else {
+        format!("error: {error}")
+    }
 }
 
 mod tests {
     // Use outside scope
     #[allow(unused_imports)]
     use super::*;
+    #[allow(unused_imports)]
+    use std::cell::RefCell;
+    #[allow(unused_imports)]
+    use crate::runtime::RuntimeVal;
+    #[allow(unused_imports)]
+    use frontend::{diagnostics::Span, grammar, lexer::Lexer};
+
+    /// Runs code, returning the global environment it was run in.
+    #[allow(dead_code)]
+    fn run_code_in_env(code: &str) -> Rc<RuntimeEnv> {
+        let env = Rc::new(RuntimeEnv::default());
+        let ast = parse_program(code).unwrap();
+
+        _ = GeneralVisitor::new(env.clone()).eval_program(&ast).unwrap();
+        env
+    }
+
+    /// Runs code with a console that reads `input`, returning everything it printed.
+    #[allow(dead_code)]
+    fn run_with_console(code: &str, input: &str) -> String {
+        let console = Rc::new(crate::console::MemoryConsole::new(input));
+        Interpreter::new(parse_program(code).unwrap()).with_console(console.clone()).run().unwrap();
+
+        console.output()
+    }
 
     #[test]
     fn test_interp_full() {
@@ -75,9 +170,373 @@ mod tests {
         run_code(code).unwrap();
     }
 
+    #[test]
+    fn test_interp_if_else() {
+        let mut code = r#"if 1 > 2 { print "Wrong!"; } else { print "Yay!"; }"#;
+        run_code(code).unwrap();
+
+        code = r#"let x = 2; if x == 1 { print "Wrong!"; } else if x == 2 { print "Yay!"; } else { print "Wrong!"; }"#;
+        run_code(code).unwrap();
+
+        // Else branch can break out of an enclosing loop
+        code = r#"while true == true { if 1 > 2 { print "Wrong!"; } else { break; } }"#;
+        run_code(code).unwrap();
+    }
+
+    #[test]
+    fn test_interp_logical() {
+        let env = run_code_in_env(r#"
+            let called = false;
+            fn f() { called = true; return true; }
+
+            let a = 1 < 2 and not 3 < 2;
+            let b = false or 1 > 2;
+            let c = false and f();
+            let d = true or f();
+        "#);
+
+        assert_eq!(env.get_var("a"), Some(RuntimeVal::Bool(true)));
+        assert_eq!(env.get_var("b"), Some(RuntimeVal::Bool(false)));
+        assert_eq!(env.get_var("c"), Some(RuntimeVal::Bool(false)));
+        assert_eq!(env.get_var("d"), Some(RuntimeVal::Bool(true)));
+
+        // Rhs is never evaluated when lhs decides the result
+        assert_eq!(env.get_var("called"), Some(RuntimeVal::Bool(false)));
+
+        // Conditions can be any bool expression, but only bools
+        assert!(run_code("let x = true; if x and not false { print x; }").is_ok());
+        assert!(run_code("if 1 { print 1; }").is_err());
+        assert!(run_code("let x = 1 and true;").is_err());
+    }
+
+    #[test]
+    fn test_interp_list() {
+        let env = run_code_in_env(r#"
+            let xs = [1, 2, 3, 4];
+            let shared = xs;
+            xs[0] = 5;
+            xs[-1] = 6;
+
+            let first = shared[0];
+            let last = xs[-1];
+            let middle = xs[1:3];
+            let tail = xs[-2:];
+            let all = xs[:];
+        "#);
+
+        let list = |items: Vec<f64>| Some(RuntimeVal::List(Rc::new(RefCell::new(
+            items.into_iter().map(RuntimeVal::Num).collect()
+        ))));
+
+        // Assigned lists share their items
+        assert_eq!(env.get_var("shared"), list(vec![5.0, 2.0, 3.0, 6.0]));
+        assert_eq!(env.get_var("first"), Some(RuntimeVal::Num(5.0)));
+        assert_eq!(env.get_var("last"), Some(RuntimeVal::Num(6.0)));
+        assert_eq!(env.get_var("middle"), list(vec![2.0, 3.0]));
+        assert_eq!(env.get_var("tail"), list(vec![3.0, 6.0]));
+        assert_eq!(env.get_var("all"), list(vec![5.0, 2.0, 3.0, 6.0]));
+    }
+
+    #[test]
+    fn test_interp_list_errors() {
+        assert!(run_code("let xs = [1]; print xs[1];").is_err());
+        assert!(run_code("let xs = [1]; print xs[-2];").is_err());
+        assert!(run_code("let xs = [1]; xs[1] = 2;").is_err());
+        assert!(run_code("let xs = [1]; print xs[0.5];").is_err());
+        assert!(run_code("let xs = [1, 2]; print xs[2:1];").is_err());
+        assert!(run_code("let x = 1; print x[0];").is_err());
+
+        // Errors show values the way `print` does
+        let error = run_code("let m = {\"a\": [1]}; print m[1:];").unwrap_err();
+        assert_eq!(error.downcast_ref::<Diagnostic>().unwrap().message, "only lists can be sliced, but tried to slice {\"a\": [1]}");
+    }
+
+    #[test]
+    fn test_interp_list_cycles() {
+        // Lists holding themselves print the repeat as `[...]`
+        let output = run_with_console("let xs = [1, 2]; xs[0] = xs; print xs; print [xs, xs];", "");
+        assert_eq!(output, "[[...], 2]\n[[[...], 2], [[...], 2]]\n");
+
+        // And can be compared without recursing forever
+        let output = run_with_console(r#"
+            let xs = [1]; xs[0] = xs;
+            let ys = [1]; ys[0] = ys;
+            print xs == xs;
+            print xs == ys;
+            print xs != [xs, 2];
+        "#, "");
+        assert_eq!(output, "true\ntrue\ntrue\n");
+    }
+
+    #[test]
+    fn test_interp_map_cycles() {
+        // Maps holding themselves print the repeat as `{...}`, even through lists
+        let output = run_with_console(r#"
+            let m = {"n": 1};
+            m["self"] = m;
+            m.items = [m];
+            print m;
+        "#, "");
+        assert_eq!(output, "{\"n\": 1, \"self\": {...}, \"items\": [{...}]}\n");
+
+        // And can be compared without recursing forever
+        let output = run_with_console(r#"
+            let a = {}; a["self"] = a;
+            let b = {}; b["self"] = b;
+            print a == a;
+            print a == b;
+            print a != {"self": 1};
+        "#, "");
+        assert_eq!(output, "true\ntrue\ntrue\n");
+
+        let error = run_code("let m = {}; m[[1]] = 2;").unwrap_err();
+        assert_eq!(error.downcast_ref::<Diagnostic>().unwrap().message, "map keys must be strings, but got [1]");
+    }
+
+    #[test]
+    fn test_interp_map() {
+        let env = run_code_in_env(r#"
+            let m = {"name": "x", "n": 3};
+            let shared = m;
+            m["n"] = 4;
+            m.extra = true;
+            m.name = "y";
+
+            let name = shared.name;
+            let n = m["n"];
+            let same = m == {"extra": true, "n": 4, "name": "y"};
+        "#);
+
+        assert_eq!(env.get_var("name"), Some(RuntimeVal::Str("y".to_string())));
+        assert_eq!(env.get_var("n"), Some(RuntimeVal::Num(4.0)));
+        assert_eq!(env.get_var("same"), Some(RuntimeVal::Bool(true)));
+
+        // Keys keep the order they were first inserted in
+        if let Some(RuntimeVal::Map(m)) = env.get_var("m") {
+            let keys: Vec<_> = m.borrow().iter().map(|(k, _)| k.clone()).collect();
+            assert_eq!(keys, vec!["name", "n", "extra"]);
+        } else {
+            panic!("m should be a map!");
+        }
+
+        assert!(run_code(r#"let m = {"a": 1}; print m.b;"#).is_err());
+        assert!(run_code(r#"let m = {1: 1};"#).is_err());
+    }
+
+    #[test]
+    fn test_interp_for() {
+        let env = run_code_in_env(r#"
+            let total = 0;
+            for i in 0..5 {
+                if i == 1 { continue; }
+                if i == 4 { break; }
+                total = total + i;
+            }
+
+            let list_total = 0;
+            let xs = [1, 2, 3];
+            for i, x in xs {
+                list_total = list_total + i * x;
+            }
+
+            let key = "";
+            let values = 0;
+            let m = {"a": 1, "b": 2};
+            for k in m { if k == "b" { key = k; } }
+            for k, v in m { values = values + v; }
+
+            let chars = 0;
+            for ch in "abc" {
+                if ch == "b" { chars = chars + 10; } else { chars = chars + 1; }
+            }
+        "#);
+
+        assert_eq!(env.get_var("total"), Some(RuntimeVal::Num(5.0)));
+        assert_eq!(env.get_var("list_total"), Some(RuntimeVal::Num(8.0)));
+        assert_eq!(env.get_var("key"), Some(RuntimeVal::Str("b".to_string())));
+        assert_eq!(env.get_var("values"), Some(RuntimeVal::Num(3.0)));
+        assert_eq!(env.get_var("chars"), Some(RuntimeVal::Num(12.0)));
+
+        // Loop names are local to each iteration
+        assert_eq!(env.get_var("i"), None);
+
+        let error = run_code("for x in true { print x; }").unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<Diagnostic>().unwrap().message,
+            "only ranges, lists, maps, and strings can be iterated over, but tried to iterate over true"
+        );
+
+        // Range bounds must be whole numbers that can be counted up from exactly, and are found where they are written
+        let error = run_code("let x = 0.5;\nfor i in x..3 { print i; }").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.message, "range bounds must be whole numbers, but got 0.5");
+        assert_eq!(diagnostic.span, Span::new(22, 23));
+        assert!(run_code("for i in 0..1 / 0 { print i; }").is_err());
+        assert!(run_code("for i in 0..\"3\" { print i; }").is_err());
+        assert!(run_code("for i in 9007199254740993..9007199254740995 { print i; }").is_err());
+        assert!(run_code("for i in -3..-1 { print i; }").is_ok());
+    }
+
+    #[test]
+    fn test_interp_for_return() {
+        let env = run_code_in_env(r#"
+            fn find(xs, target) {
+                for i, x in xs {
+                    if x == target { return i; }
+                }
+                return -1;
+            }
+
+            let found = find([5, 6, 7], 6);
+            let missing = find([5, 6, 7], 8);
+        "#);
+
+        assert_eq!(env.get_var("found"), Some(RuntimeVal::Num(1.0)));
+        assert_eq!(env.get_var("missing"), Some(RuntimeVal::Num(-1.0)));
+    }
+
+    #[test]
+    fn test_interp_str_interpolation() {
+        let env = run_code_in_env(r#"
+            let name = "Bob";
+            let n = 3;
+            let s = "Hello {name}, you have {n * 2} items: {[1, 2]} {{ok}}";
+        "#);
+
+        assert_eq!(
+            env.get_var("s"),
+            Some(RuntimeVal::Str("Hello Bob, you have 6 items: [1, 2] {ok}".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_interp_while() {
+        let mut code = r#"let x = true; while x == true { x = false; }"#;
+        run_code(code).unwrap();
+
+        code = r#"while true == true { if 1 < 2 { break; } print "Unreachable!"; }"#;
+        run_code(code).unwrap();
+
+        code = r#"let x = true; while x == true { x = false; continue; print "Unreachable!"; }"#;
+        run_code(code).unwrap();
+
+        // Loop signals are errors outside of a loop
+        assert!(run_code("break;").is_err());
+        assert!(run_code("continue;").is_err());
+    }
+
+    #[test]
+    fn test_interp_fn() {
+        let env = run_code_in_env(r#"
+            fn fact(n) {
+                if n < 2 { return 1; }
+                return n * fact(n - 1);
+            }
+
+            fn nothing() { }
+
+            let x = fact(5);
+            let y = nothing();
+        "#);
+
+        assert_eq!(env.get_var("x"), Some(RuntimeVal::Num(120.0)));
+        assert_eq!(env.get_var("y"), Some(RuntimeVal::Null));
+
+        // Params are local to the call
+        assert_eq!(env.get_var("n"), None);
+    }
+
+    #[test]
+    fn test_interp_scope() {
+        // Assignments update the nearest scope defining a var, and params shadow vars in enclosing scopes
+        let env = run_code_in_env(r#"
+            let x = 1;
+            { x = 2; let y = 3; }
+            if true == true { let z = 4; }
+
+            fn shadow(x) { x = 10; return x; }
+            let s = shadow(5);
+        "#);
+
+        assert_eq!(env.get_var("x"), Some(RuntimeVal::Num(2.0)));
+        assert_eq!(env.get_var("s"), Some(RuntimeVal::Num(10.0)));
+
+        // Block-local vars are dropped with their block
+        assert_eq!(env.get_var("y"), None);
+        assert_eq!(env.get_var("z"), None);
+    }
+
+    #[test]
+    fn test_interp_scope_fn() {
+        // Nested functions see the scope they were declared in, and block-local functions can recurse
+        let env = run_code_in_env(r#"
+            fn outer() {
+                let count = 0;
+                fn inc() { count = count + 1; }
+                inc();
+                inc();
+                return count;
+            }
+            let c = outer();
+
+            let r = 0;
+            {
+                fn down(n) { if n < 1 { return 0; } return down(n - 1); }
+                r = down(3) + 1;
+            }
+        "#);
+
+        assert_eq!(env.get_var("c"), Some(RuntimeVal::Num(2.0)));
+        assert_eq!(env.get_var("r"), Some(RuntimeVal::Num(1.0)));
+        assert_eq!(env.get_var("down"), None);
+
+        // Functions don't keep the scope they were declared in alive, so it is dropped once it ends
+        assert_eq!(Rc::strong_count(&env), 1);
+        let env = run_code_in_env("fn outer() { let xs = [1]; fn inner() { return xs; } return inner; } let f = outer();");
+        let Some(RuntimeVal::Function(f)) = env.get_var("f") else { panic!("`f` should be a function") };
+        assert_eq!(f.env.unwrap().strong_count(), 0);
+        assert!(run_code("fn outer() { fn inner() { return 1; } return inner; } let f = outer(); f();").is_err());
+    }
+
+    #[test]
+    fn test_interp_fn_errors() {
+        // Wrong number of arguments
+        assert!(run_code("fn f(a) { return a; } f(1, 2);").is_err());
+        // Calling something that isn't a function, or isn't declared
+        let message = |code: &str| run_code(code).unwrap_err().downcast_ref::<Diagnostic>().unwrap().message.clone();
+        assert_eq!(message("let x = 1; x();"), "`x` is not a function");
+        assert_eq!(message("nope();"), "undefined function `nope`");
+        // Returning outside of a function
+        assert!(run_code("return 1;").is_err());
+        // Loop signals don't escape function calls
+        assert!(run_code("fn f() { break; } while true == true { f(); }").is_err());
+    }
+
+    #[test]
+    fn test_interp_let_const() {
+        let env = run_code_in_env(r#"
+            let x = 1;
+            const c = 2;
+            { let x = 3; x = 4; }
+        "#);
+
+        // Block-local declaration shadows the outer var
+        assert_eq!(env.get_var("x"), Some(RuntimeVal::Num(1.0)));
+        assert_eq!(env.get_var("c"), Some(RuntimeVal::Num(2.0)));
+
+        // Assigning to an undeclared var
+        assert!(run_code("x = 1;").is_err());
+        // Assigning to or redeclaring a constant
+        assert!(run_code("const c = 1; c = 2;").is_err());
+        assert!(run_code("const c = 1; { c = 2; }").is_err());
+        assert!(run_code("const c = 1; let c = 2;").is_err());
+        // Shadowing a constant in an inner scope is allowed
+        assert!(run_code("const c = 1; { let c = 2; c = 3; }").is_ok());
+    }
+
     #[test]
     fn test_interp_bool() {
-        let code = "x = true;";
+        let code = "let x = true;";
 
         run_code(code).unwrap();
     }
@@ -93,21 +552,251 @@ mod tests {
     }
 
     #[test]
-    fn test_interp_print_read() {
-        // Complete code file for interpreter to run
-        let code = r#"
-            print read;
-        "#;
+    fn test_interp_error_spans() {
+        // Runtime errors are found at the innermost expression that failed
+        let error = run_code("let x = 1;\nprint [x, -\"a\"];").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.span, Span::new(21, 25));
 
-        _ = run_code(code).unwrap();
+        // Conditions that aren't bools are found at the condition, not the whole statement
+        let error = run_code("let x = 1;\nif [x] { print x; }").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.message, "condition must evaluate to a bool, but evaluated to [1]");
+        assert_eq!(diagnostic.span, Span::new(14, 17));
+
+        // Every parse error is found at its unexpected token
+        let error = run_code("let x = 1 2;\nprint x +;").unwrap_err();
+        let diagnostics = error.downcast_ref::<Diagnostics>().unwrap();
+        assert_eq!(
+            diagnostics.0.iter().map(|d| d.span).collect::<Vec<_>>(),
+            vec![Span::new(10, 11), Span::new(22, 23)]
+        );
+    }
+
+    #[test]
+    fn test_interp_idents() {
+        // Vars are looked up as soon as they are used, so every operator sees their values
+        let output = eval_code(r#"
+            let x = 20;
+            let y = x;
+            x = x + 1;
+            let z = -y * 2 + x;
+            let s = "{x}";
+            z
+        "#).unwrap();
+
+        assert_eq!(output.value, RuntimeVal::Num(-19.0));
+        assert_eq!(output.get_var::<f64>("y").unwrap(), 20.0);
+        assert_eq!(output.get_var::<String>("s").unwrap(), "21");
+
+        // Undefined vars are found where they are used, even if the value would never be needed
+        let error = run_code("let a = 1;\nlet b = a + nope;").unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.message, "undefined variable `nope`");
+        assert_eq!(diagnostic.span, Span::new(23, 27));
+        assert!(run_code("nope;").is_err());
+    }
+
+    #[test]
+    fn test_interp_sh() {
+        let env = run_code_in_env(r#"
+            let r = sh("echo hi; echo oops >&2; exit 2");
+            let out = r.stdout;
+            let err = r.stderr;
+            let code = r.code;
+        "#);
+
+        assert_eq!(env.get_var("out"), Some(RuntimeVal::Str("hi\n".to_string())));
+        assert_eq!(env.get_var("err"), Some(RuntimeVal::Str("oops\n".to_string())));
+        assert_eq!(env.get_var("code"), Some(RuntimeVal::Num(2.0)));
+
+        // Non-zero exit codes are only errors when checked
+        assert!(run_code(r#"sh("exit 1", {"check": true});"#).is_err());
+        assert!(run_code(r#"sh("exit 0", {"check": true});"#).is_ok());
+        assert!(run_code(r#"sh("true", {"chek": true});"#).is_err());
+
+        // Declared functions shadow builtins
+        let env = run_code_in_env("fn sh(x) { return x; } let y = sh(3);");
+        assert_eq!(env.get_var("y"), Some(RuntimeVal::Num(3.0)));
+    }
+
+    #[test]
+    fn test_interp_exec() {
+        let env = run_code_in_env(r#"
+            let out = exec("printf", ["%s|", "a b; echo pwned", 2, true]).stdout;
+            let code = exec("sh", ["-c", "exit 4"]).code;
+        "#);
+
+        assert_eq!(env.get_var("out"), Some(RuntimeVal::Str("a b; echo pwned|2|true|".to_string())));
+        assert_eq!(env.get_var("code"), Some(RuntimeVal::Num(4.0)));
+
+        assert!(run_code(r#"exec("false", [], {"check": true});"#).is_err());
+        assert!(run_code(r#"exec("printf", [[1]]);"#).is_err());
+        assert!(run_code(r#"exec("seel-missing-program");"#).is_err());
+    }
+
+    #[test]
+    fn test_interp_pipe() {
+        let env = run_code_in_env(r#"
+            let sorted = pipe([["sort"], ["uniq", "-c"], ["wc", "-l"]], {"stdin": "b\na\nb\n"}).stdout;
+            let vars = sh("echo $A $B", {"env": {"A": "x", "B": 2}}).stdout;
+            let dir = exec("pwd", [], {"cwd": "/"}).stdout;
+        "#);
+
+        assert_eq!(env.get_var("sorted").map(|v| v.to_string().trim().to_string()), Some("2".to_string()));
+        assert_eq!(env.get_var("vars"), Some(RuntimeVal::Str("x 2\n".to_string())));
+        assert_eq!(env.get_var("dir"), Some(RuntimeVal::Str("/\n".to_string())));
+
+        assert!(run_code(r#"pipe([["sh", "-c", "exit 1"], ["cat"]], {"check": true});"#).is_ok());
+        assert!(run_code(r#"pipe([["cat"], ["false"]], {"check": true});"#).is_err());
+        assert!(run_code(r#"pipe([[]]);"#).is_err());
+        assert!(run_code(r#"sh("sleep 5", {"timeout": 0.05});"#).is_err());
+        assert!(run_code(r#"sh("true", {"timeout": "soon"});"#).is_err());
+    }
+
+    #[test]
+    fn test_interp_files_env() {
+        let path = std::env::temp_dir().join(format!("seel-test-files-env-{}.txt", std::process::id()));
+        let env = run_code_in_env(&format!(r#"
+            write_file({path:?}, "hi");
+            let contents = read_file({path:?});
+            let missing = env_var("SEEL_TEST_MISSING_VAR");
+        "#));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(env.get_var("contents"), Some(RuntimeVal::Str("hi".to_string())));
+        assert_eq!(env.get_var("missing"), Some(RuntimeVal::Null));
+        assert!(run_code(r#"read_file("/seel/missing/file");"#).is_err());
+    }
+
+    #[test]
+    fn test_interp_permissions() {
+        let run_with = |code: &str, permissions: Permissions| {
+            Interpreter::new(parse_program(code).unwrap()).with_permissions(permissions).run()
+        };
+
+        let error = run_with("let x = 1;\nsh(\"ls\");", Permissions::sandboxed()).unwrap_err();
+        let diagnostic = error.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!(diagnostic.message, "permission denied: not allowed to run programs");
+        assert_eq!(diagnostic.span, Span::new(11, 19));
+
+        // Only allowed programs can run, and shell code can run anything, so it needs `sh` to be allowed
+        let some = || Permissions::sandboxed().allow_exec(["printf"]);
+        assert!(run_with(r#"exec("printf", ["x"]);"#, some()).is_ok());
+        assert!(run_with(r#"pipe([["printf", "x"], ["cat"]]);"#, some()).is_err());
+        assert!(run_with("`printf x`;", some()).is_err());
+        assert!(run_with("'\"printf x\"';", some()).is_err());
+        assert!(run_with(r#"exec("printf", ["x"], {"env": {"PATH": "/tmp"}});"#, some()).is_err());
+        assert!(run_with(r#"exec("printf", ["x"], {"stdout": "/tmp/out"});"#, some()).is_err());
+
+        assert!(run_with(r#"read_file("/etc/hostname");"#, Permissions::sandboxed()).is_err());
+        assert!(run_with(r#"write_file("/tmp/x", "");"#, Permissions::sandboxed()).is_err());
+        assert!(run_with(r#"env_var("HOME");"#, Permissions::sandboxed()).is_err());
+        assert!(run_with("let x = read;", Permissions::sandboxed()).is_err());
+        assert!(run_with(r#"env_var("HOME");"#, Permissions { env: true, ..Permissions::sandboxed() }).is_ok());
+    }
+
+    #[test]
+    fn test_interp_limits() {
+        use std::time::Duration;
+        use crate::limits::{LimitError, DEFAULT_MAX_CALL_DEPTH};
+
+        let run_with = |code: &str, limits: Limits| {
+            Interpreter::new(parse_program(code).unwrap()).with_limits(limits).run()
+        };
+        let limit_error = |result: anyhow::Result<()>| result.unwrap_err().downcast_ref::<LimitError>().cloned();
+
+        // Recursion is stopped before it can overflow the stack
+        let recurse = |n: usize| format!("fn f(n) {{ if n == 0 {{ return 0; }} return f(n - 1) + 1; }} let x = f({n});");
+        assert!(run_with(&recurse(DEFAULT_MAX_CALL_DEPTH - 1), Limits::default()).is_ok());
+        assert_eq!(limit_error(run_with(&recurse(DEFAULT_MAX_CALL_DEPTH), Limits::default())), Some(LimitError::CallDepth(DEFAULT_MAX_CALL_DEPTH)));
+        assert_eq!(limit_error(run_with(&recurse(10), Limits { max_call_depth: Some(5), ..Limits::none() })), Some(LimitError::CallDepth(5)));
+
+        // Nested code is stopped before it overflows the stack, whatever size the stack is
+        let on_stack = |size: usize, code: String, limits: Limits| {
+            let ast = parse_program(&code).unwrap();
+            std::thread::Builder::new()
+                .stack_size(size)
+                .spawn(move || Interpreter::new(ast).with_limits(limits).run().map_err(|e| e.downcast_ref::<LimitError>().cloned()))
+                .unwrap()
+                .join()
+                .unwrap()
+        };
+        let nested = |n: usize| format!(
+            "let xs = [1]; fn f(n) {{ if n == 0 {{ return 0; }} for i in 0..1 {{ while true {{ if true {{ {{ return f(n - 1) + xs[0]; }} }} }} }} }} let x = f({n});"
+        );
+        let mb = 1024 * 1024;
+        assert!(matches!(on_stack(2 * mb, nested(DEFAULT_MAX_CALL_DEPTH - 1), Limits::default()), Ok(()) | Err(Some(LimitError::Stack))));
+        assert_eq!(on_stack(2 * mb, nested(100_000), Limits::none()), Err(Some(LimitError::Stack)));
+        assert_eq!(on_stack(2 * mb, recurse(100_000), Limits::none()), Err(Some(LimitError::Stack)));
+        let sum = |terms: usize| format!("print {};", vec!["1"; terms].join(" + "));
+        assert_eq!(on_stack(2 * mb, sum(200), Limits::none()), Ok(()));
+
+        // Code nested too deeply to even run is a syntax error
+        assert!(eval_code(&sum(5000)).unwrap_err().downcast_ref::<Diagnostics>().is_some());
+
+        let forever = "let i = 0; while true { i = i + 1; }";
+        assert_eq!(limit_error(run_with(forever, Limits { max_steps: Some(1000), ..Limits::none() })), Some(LimitError::Steps(1000)));
+        let timeout = Duration::from_millis(50);
+        assert_eq!(limit_error(run_with(forever, Limits { timeout: Some(timeout), ..Limits::none() })), Some(LimitError::Timeout(timeout)));
+
+        // Commands are stopped once they outlive the timeout, even if given a longer one
+        let started = std::time::Instant::now();
+        let timeout = Duration::from_millis(100);
+        for code in [
+            r#"sh("sleep 3"); print 1;"#,
+            r#"exec("sleep", [3]); print 1;"#,
+            r#"pipe([["sleep", "3"], ["cat"]], {"timeout": 10}); print 1;"#,
+            "`sleep 3`; print 1;",
+            // Bash statements can't be stopped early, but still fail once they return
+            "'\"sleep 0.2\"'; print 1;"
+        ] {
+            assert_eq!(limit_error(run_with(code, Limits { timeout: Some(timeout), ..Limits::none() })), Some(LimitError::Timeout(timeout)));
+        }
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let small = || Limits { max_collection_len: Some(2), ..Limits::none() };
+        assert!(run_with("let xs = [1, 2]; let m = {\"a\": 1}; m.b = 2; m.a = 3;", small()).is_ok());
+        assert_eq!(limit_error(run_with("let xs = [1, 2, 3];", small())), Some(LimitError::CollectionLen(2)));
+        assert_eq!(limit_error(run_with("let m = {}; for i in 0..5 { m[\"{i}\"] = i; }", small())), Some(LimitError::CollectionLen(2)));
+
+        // Limit errors still say where they happened
+        let error = run_with("let x = 1;\nlet xs = [1, 2, 3];", small()).unwrap_err();
+        assert_eq!(error.downcast_ref::<Diagnostic>().unwrap().span, Span::new(20, 29));
+    }
+
+    #[test]
+    fn test_interp_shell_code() {
+        let env = run_code_in_env(r#"
+            let file = "a b; echo pwned";
+            let files = ["it's", "$HOME"];
+            let one = `printf %s {file}`.stdout;
+            let many = `printf '%s|' {files} {{x}}`.stdout;
+            let code = `exit 5`.code;
+            let fallback = `printf %s ${{SEEL_TEST_MISSING_VAR:-none}}`.stdout;
+        "#);
+
+        // Inserted values stay single words, no matter what they contain
+        assert_eq!(env.get_var("one"), Some(RuntimeVal::Str("a b; echo pwned".to_string())));
+        assert_eq!(env.get_var("many"), Some(RuntimeVal::Str("it's|$HOME|{x}|".to_string())));
+        assert_eq!(env.get_var("code"), Some(RuntimeVal::Num(5.0)));
+
+        // Doubled braces reach the shell as single braces
+        assert_eq!(env.get_var("fallback"), Some(RuntimeVal::Str("none".to_string())));
+    }
+
+    #[test]
+    fn test_interp_print_read() {
+        let output = run_with_console("let name = read; print name; print read; print read;", "seel\nlast");
+        assert_eq!(output, "seel\n\nlast\n\n");
     }
 
     #[test]
     fn test_interp_print() {
-        assert!(run_code("print 23;").is_ok());
-        assert!(run_code("print 23 * 42 + 56;").is_ok());
-        assert!(run_code("print (23 + 42) * 56;").is_ok());
-        _ = run_code("let x = 23; print x;").unwrap();
+        assert_eq!(run_with_console("print 23;", ""), "23\n");
+        assert_eq!(run_with_console("print 23 * 42 + 56;", ""), "1022\n");
+        assert_eq!(run_with_console("print (23 + 42) * 56;", ""), "3640\n");
+        assert_eq!(run_with_console(r#"let x = 23; print x; print [x, "y"];"#, ""), "23\n[23, \"y\"]\n");
     }
 
     #[test]
@@ -115,9 +804,34 @@ mod tests {
         // Create parser
         let parser = grammar::ProgramParser::new();
         // Create interpreter that parses assign statement
-        let interp = Interpreter::new(parser.parse("let x = 23;").unwrap());
+        let interp = Interpreter::new(parser.parse(&mut Vec::new(), Lexer::new("let x = 23;")).unwrap());
 
         // Run interpreter
-        _ = interp.run().unwrap();
+        let output = interp.eval().unwrap();
+        assert_eq!(output.globals.get("x"), Some(&RuntimeVal::Num(23.0)));
+    }
+
+    #[test]
+    fn test_interp_eval_code() {
+        let output = eval_code(r#"
+            fn square(n) { return n * n; }
+            let xs = [1, 2, 3];
+            let total = 0;
+            for x in xs { total = total + square(x); }
+            total + 1
+        "#).unwrap();
+
+        assert_eq!(output.value, RuntimeVal::Num(15.0));
+        assert_eq!(output.get_var::<f64>("total").unwrap(), 14.0);
+        assert_eq!(output.get_var::<Vec<f64>>("xs").unwrap(), vec![1.0, 2.0, 3.0]);
+        assert!(output.get_var::<f64>("x").is_err());
+        assert!(matches!(output.globals.get("square"), Some(RuntimeVal::Function(_))));
+
+        // The last expression statement gives the value, even if other statements follow it
+        assert_eq!(eval_code("let x = 1; x").unwrap().value, RuntimeVal::Num(1.0));
+        assert_eq!(eval_code("let x = 1; x; let y = 2;").unwrap().value, RuntimeVal::Num(1.0));
+        assert_eq!(eval_code("1; if true { 2; }").unwrap().value, RuntimeVal::Num(1.0));
+        assert_eq!(eval_code("let x = 1;").unwrap().value, RuntimeVal::Null);
+        assert_eq!(eval_code("").unwrap(), RunOutput { value: RuntimeVal::Null, globals: HashMap::new() });
     }
 }
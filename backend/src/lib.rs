@@ -1,6 +1,13 @@
 
 
 // Mod declarations
+pub mod builtins;
+pub mod console;
+pub mod engine;
 pub mod interpreter;
+pub mod limits;
+pub mod permissions;
+pub mod repl;
 pub mod runtime;
+pub mod shell;
 pub mod visitor;
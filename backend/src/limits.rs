@@ -0,0 +1,68 @@
+/*!
+    Contains the limits on how much work code is allowed to do.
+*/
+
+use std::{fmt, time::Duration};
+
+/// The deepest function calls can be nested by default.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 128;
+
+/// Limits on how much work code can do, where `None` means there is no limit.
+///
+/// Only the call depth is limited by default. Whatever the limits, code nested too deeply to fit on the stack
+/// stops with `LimitError::Stack` instead of crashing the interpreter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The number of statements and expressions that can be evaluated.
+    pub max_steps: Option<u64>,
+    /// How deep function calls can be nested.
+    pub max_call_depth: Option<usize>,
+    /// How long code can run for.
+    pub timeout: Option<Duration>,
+    /// The number of items a list or map can hold.
+    pub max_collection_len: Option<usize>
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_steps: None,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            timeout: None,
+            max_collection_len: None
+        }
+    }
+}
+
+impl Limits {
+    /// Returns limits that don't limit anything, not even the call depth.
+    pub fn none() -> Self {
+        Self { max_call_depth: None, ..Self::default() }
+    }
+}
+
+/// The error returned when code goes over one of its limits.
+///
+/// It can be found by downcasting an error returned by the interpreter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LimitError {
+    Steps(u64),
+    CallDepth(usize),
+    Stack,
+    Timeout(Duration),
+    CollectionLen(usize)
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Steps(max) => write!(f, "limit exceeded: evaluated more than {max} steps"),
+            Self::CallDepth(max) => write!(f, "limit exceeded: function calls nested more than {max} deep"),
+            Self::Stack => write!(f, "limit exceeded: statements and expressions nested too deeply to fit on the stack"),
+            Self::Timeout(max) => write!(f, "limit exceeded: ran for longer than {}s", max.as_secs_f64()),
+            Self::CollectionLen(max) => write!(f, "limit exceeded: a list or map held more than {max} items")
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
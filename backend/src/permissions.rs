@@ -0,0 +1,141 @@
+/*!
+    Contains the permissions that control what code is allowed to do outside of the interpreter.
+*/
+
+use anyhow::anyhow;
+
+/// The name of the program shell code is run by, which must be allowed to run shell code.
+pub const SHELL_PROGRAM: &str = "sh";
+
+/// Something code can do outside of the interpreter that might need to be denied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    FileRead,
+    FileWrite,
+    Env,
+    Stdin
+}
+
+impl Capability {
+    /// Describes what the capability allows.
+    fn describe(self) -> &'static str {
+        match self {
+            Self::FileRead => "read files",
+            Self::FileWrite => "write files",
+            Self::Env => "access environment variables",
+            Self::Stdin => "read standard input"
+        }
+    }
+}
+
+/// Which programs code is allowed to run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExecAccess {
+    /// Any program can be run.
+    #[default]
+    All,
+    /// Only programs with these names can be run, so none can be if it is empty.
+    Only(Vec<String>)
+}
+
+/// What code is allowed to do outside of the interpreter.
+///
+/// Everything is allowed by default, while `sandboxed` allows nothing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    pub exec: ExecAccess,
+    pub file_read: bool,
+    pub file_write: bool,
+    pub env: bool,
+    pub stdin: bool
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            exec: ExecAccess::All,
+            file_read: true,
+            file_write: true,
+            env: true,
+            stdin: true
+        }
+    }
+}
+
+impl Permissions {
+    /// Returns permissions that allow nothing.
+    pub fn sandboxed() -> Self {
+        Self {
+            exec: ExecAccess::Only(Vec::new()),
+            file_read: false,
+            file_write: false,
+            env: false,
+            stdin: false
+        }
+    }
+
+    /// Returns these permissions with `programs` being the only programs allowed to run.
+    pub fn allow_exec<S: Into<String>>(mut self, programs: impl IntoIterator<Item = S>) -> Self {
+        self.exec = ExecAccess::Only(programs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Returns true if `capability` is allowed.
+    pub fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::FileRead => self.file_read,
+            Capability::FileWrite => self.file_write,
+            Capability::Env => self.env,
+            Capability::Stdin => self.stdin
+        }
+    }
+
+    /// Returns true if the program named `program` is allowed to run.
+    pub fn allows_exec(&self, program: &str) -> bool {
+        match &self.exec {
+            ExecAccess::All => true,
+            ExecAccess::Only(programs) => programs.iter().any(|p| p == program)
+        }
+    }
+
+    /// Returns an error if `capability` isn't allowed.
+    pub fn check(&self, capability: Capability) -> anyhow::Result<()> {
+        match self.allows(capability) {
+            true => Ok(()),
+            false => Err(anyhow!("permission denied: not allowed to {}", capability.describe()))
+        }
+    }
+
+    /// Returns an error if the program named `program` isn't allowed to run.
+    pub fn check_exec(&self, program: &str) -> anyhow::Result<()> {
+        match (&self.exec, self.allows_exec(program)) {
+            (_, true) => Ok(()),
+            (ExecAccess::Only(programs), false) if programs.is_empty() => Err(anyhow!("permission denied: not allowed to run programs")),
+            (_, false) if program == SHELL_PROGRAM => Err(anyhow!("permission denied: not allowed to run shell code, as '{SHELL_PROGRAM}' isn't an allowed program")),
+            (_, false) => Err(anyhow!("permission denied: not allowed to run program '{program}'"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+
+    #[test]
+    fn test_permissions() {
+        let all = Permissions::default();
+        assert!(all.allows_exec("rm"));
+        assert!(all.check(Capability::Stdin).is_ok());
+
+        let sandboxed = Permissions::sandboxed();
+        assert_eq!(sandboxed.check_exec("ls").unwrap_err().to_string(), "permission denied: not allowed to run programs");
+        assert_eq!(sandboxed.check(Capability::FileRead).unwrap_err().to_string(), "permission denied: not allowed to read files");
+
+        let some = Permissions::sandboxed().allow_exec(["git", "ls"]);
+        assert!(some.check_exec("git").is_ok());
+        assert!(some.check_exec("/bin/ls").is_err());
+        assert_eq!(some.check_exec("rm").unwrap_err().to_string(), "permission denied: not allowed to run program 'rm'");
+        assert!(some.check_exec(SHELL_PROGRAM).unwrap_err().to_string().contains("shell code"));
+    }
+}
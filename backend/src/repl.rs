@@ -0,0 +1,209 @@
+/*!
+    Contains the REPL, which evaluates code as it is typed.
+*/
+
+use std::io::{self, BufRead, Write};
+use frontend::{lexer::{Lexer, Tok}, parser::parse_program};
+use crate::{engine::Engine, interpreter::render_error, limits::Limits, permissions::Permissions, runtime::RuntimeVal};
+
+/// The name errors found in REPL input are shown under.
+const REPL_NAME: &str = "<repl>";
+
+/// How the tokens that can span lines start, so input ending inside of one isn't complete yet.
+const MULTILINE_OPENERS: [&str; 4] = ["\"\"\"", "r\"", "/*", "`"];
+
+/// The meta-commands of the REPL.
+const HELP: &str = "\
+:ast <code>  Shows the ast of code without running it
+:vars        Shows every global variable
+:reset       Drops every variable
+:help        Shows this message
+:quit        Exits the REPL";
+
+/// A REPL that keeps its variables between inputs.
+#[derive(Clone, Debug, Default)]
+pub struct Repl {
+    /// The engine every input is evaluated by.
+    engine: Engine
+}
+
+impl Repl {
+    /// Constructs a new REPL with no variables.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns this REPL with code only allowed to do what `permissions` allow.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.engine = self.engine.with_permissions(permissions);
+        self
+    }
+
+    /// Returns this REPL with each input only allowed to do as much work as `limits` allow.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.engine = self.engine.with_limits(limits);
+        self
+    }
+
+    /// Returns true if `input` has no unclosed `{`, and doesn't end inside of a token that can span lines, so it is ready to be evaluated.
+    ///
+    /// Braces inside of strings and comments are skipped by the lexer, so they aren't counted.
+    pub fn is_complete(input: &str) -> bool {
+        let mut depth = 0;
+        for tok in Lexer::new(input) {
+            match tok {
+                Ok((_, Tok::LBrace, _)) => depth += 1,
+                Ok((_, Tok::RBrace, _)) => depth -= 1,
+                Err(e) if MULTILINE_OPENERS.iter().any(|opener| input[e.start..].starts_with(opener)) => return false,
+                _ => {}
+            }
+        }
+
+        depth <= 0
+    }
+
+    /// Handles a complete input, returning the text to show for it if any, or the rendered error it caused.
+    pub fn handle(&mut self, input: &str) -> Result<Option<String>, String> {
+        let input = input.trim();
+        let (command, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+
+        match command {
+            ":ast" => parse_program(rest)
+                .map(|ast| Some(format!("{ast:#?}")))
+                .map_err(|e| e.render(REPL_NAME, rest)),
+            ":vars" => Ok(Some(self.describe_vars())),
+            ":reset" => {
+                self.engine.reset();
+                Ok(None)
+            }
+            ":help" => Ok(Some(HELP.to_string())),
+            _ if command.starts_with(':') => Err(format!("error: unknown command '{command}', use :help to list commands")),
+            _ => self.eval(input)
+                .map(|val| val.map(|val| val.to_string()))
+                .map_err(|e| render_error(&e, REPL_NAME, input))
+        }
+    }
+
+    /// Evaluates `code`, returning the value of its last expression statement if it isn't null.
+    pub fn eval(&self, code: &str) -> anyhow::Result<Option<RuntimeVal>> {
+        match self.engine.eval(code)? {
+            RuntimeVal::Null => Ok(None),
+            val => Ok(Some(val))
+        }
+    }
+
+    /// Describes every global variable, sorted by name.
+    fn describe_vars(&self) -> String {
+        let symbols = self.engine.env().symbols.borrow();
+        if symbols.is_empty() {
+            return "no variables declared".to_string();
+        }
+
+        let mut names: Vec<_> = symbols.keys().collect();
+        names.sort();
+        names.iter()
+            .map(|name| format!("{name} = {}", symbols[*name]))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Runs the REPL on standard input until it ends or `:quit` is entered.
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        let mut input = String::new();
+
+        loop {
+            print!("{}", if input.is_empty() { "> " } else { "... " });
+            io::stdout().flush()?;
+
+            // Stop once standard input ends
+            if io::stdin().lock().read_line(&mut input)? == 0 {
+                break;
+            }
+
+            // Keep reading lines until every `{` and multi-line token is closed
+            if !Self::is_complete(&input) {
+                continue;
+            }
+
+            match input.trim() {
+                ":quit" => break,
+                "" => {}
+                _ => match self.handle(&input) {
+                    Ok(Some(output)) => println!("{output}"),
+                    Ok(None) => {}
+                    Err(error) => eprintln!("{error}")
+                }
+            }
+            input.clear();
+        }
+
+        // Return no errors
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+
+    #[test]
+    fn test_repl_is_complete() {
+        assert!(Repl::is_complete("let x = 1;"));
+        assert!(!Repl::is_complete("fn f() {\n    if true {"));
+        assert!(Repl::is_complete("fn f() {\n    if true { }\n}"));
+
+        // Braces inside of strings and comments don't count
+        assert!(Repl::is_complete("print \"{{\"; // {"));
+        assert!(Repl::is_complete("print \"}\"; /* { */"));
+
+        // Strings, comments and shell code that can span lines are waited on until they close
+        assert!(!Repl::is_complete("let s = \"\"\"a {\n"));
+        assert!(Repl::is_complete("let s = \"\"\"a {\n}\"\"\";\n"));
+        assert!(!Repl::is_complete("/* a\n"));
+        assert!(!Repl::is_complete("let s = `ls\n"));
+        assert!(!Repl::is_complete("let s = r\"a\n"));
+
+        // Ordinary strings can't span lines, so they are left for the parser to report
+        assert!(Repl::is_complete("print \"a\n"));
+    }
+
+    #[test]
+    fn test_repl_multiline_str() {
+        let mut repl = Repl::new();
+        let input = "let s = \"\"\"a {{\nb\"\"\";\n";
+
+        assert!(!Repl::is_complete("let s = \"\"\"a {{\n"));
+        assert!(Repl::is_complete(input));
+        assert_eq!(repl.handle(input), Ok(None));
+        assert_eq!(repl.handle("s"), Ok(Some("a {\nb".to_string())));
+    }
+
+    #[test]
+    fn test_repl_keeps_env() {
+        let mut repl = Repl::new();
+
+        assert_eq!(repl.handle("let x = 20;"), Ok(None));
+        assert_eq!(repl.handle("fn double(n) {\n    return n * 2;\n}"), Ok(None));
+        assert_eq!(repl.handle("double(x) + 2"), Ok(Some("42".to_string())));
+        assert_eq!(repl.handle("x"), Ok(Some("20".to_string())));
+        assert_eq!(repl.handle(":vars"), Ok(Some("double = <fn double>\nx = 20".to_string())));
+
+        assert_eq!(repl.handle(":reset"), Ok(None));
+        assert_eq!(repl.handle(":vars"), Ok(Some("no variables declared".to_string())));
+        assert!(repl.handle("x").is_err());
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let mut repl = Repl::new();
+
+        assert!(repl.handle(":ast 1 + 2").unwrap().unwrap().contains("Binary"));
+        assert!(repl.handle(":ast 1 +").is_err());
+        assert!(repl.handle(":nope").is_err());
+
+        // Code shown with `:ast` isn't run
+        assert!(repl.handle(":ast let x = 1;").is_ok());
+        assert_eq!(repl.handle(":vars"), Ok(Some("no variables declared".to_string())));
+    }
+}
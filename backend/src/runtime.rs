@@ -2,44 +2,364 @@
     Contains things related to runtime
 */
 
-use std::{cell::RefCell, collections::HashMap};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, fmt, rc::{Rc, Weak}};
+use anyhow::anyhow;
+use frontend::parser::FnStmt;
 
 /// A runtime value.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub enum RuntimeVal {
-    Ident(String),
     Str(String),
     Num(f64),
     Bool(bool),
+    /// A range of whole numbers, from the first number up to but not including the second.
+    Range(f64, f64),
+    /// A list, shared between every value it is assigned to.
+    List(Rc<RefCell<Vec<RuntimeVal>>>),
+    /// A map, shared between every value it is assigned to.
+    Map(Rc<RefCell<RuntimeMap>>),
+    Function(FunctionVal),
+    /// A function implemented in Rust.
+    Native(NativeFn),
     Null
 }
 
+impl RuntimeVal {
+    /// Returns true if this value equals `other`, where `comparing` holds the pairs of lists and maps already being compared.
+    /// 
+    /// A pair that is already being compared is assumed to be equal, so lists and maps that contain themselves can be compared.
+    fn eq_nested(&self, other: &Self, comparing: &mut Vec<(*const (), *const ())>) -> bool {
+        match (self, other) {
+            (RuntimeVal::Str(l), RuntimeVal::Str(r)) => l == r,
+            (RuntimeVal::Num(l), RuntimeVal::Num(r)) => l == r,
+            (RuntimeVal::Bool(l), RuntimeVal::Bool(r)) => l == r,
+            (RuntimeVal::Range(lstart, lend), RuntimeVal::Range(rstart, rend)) => lstart == rstart && lend == rend,
+            (RuntimeVal::List(l), RuntimeVal::List(r)) => {
+                let pair = (Rc::as_ptr(l).cast(), Rc::as_ptr(r).cast());
+                if Rc::ptr_eq(l, r) || comparing.contains(&pair) {
+                    return true;
+                }
+
+                comparing.push(pair);
+                let (l, r) = (l.borrow(), r.borrow());
+                let equal = l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| l.eq_nested(r, comparing));
+                comparing.pop();
+                equal
+            }
+            (RuntimeVal::Map(l), RuntimeVal::Map(r)) => {
+                let pair = (Rc::as_ptr(l).cast(), Rc::as_ptr(r).cast());
+                if Rc::ptr_eq(l, r) || comparing.contains(&pair) {
+                    return true;
+                }
+
+                comparing.push(pair);
+                let equal = l.borrow().eq_nested(&r.borrow(), comparing);
+                comparing.pop();
+                equal
+            }
+            (RuntimeVal::Function(l), RuntimeVal::Function(r)) => l == r,
+            (RuntimeVal::Native(l), RuntimeVal::Native(r)) => l == r,
+            (RuntimeVal::Null, RuntimeVal::Null) => true,
+            _ => false
+        }
+    }
+
+    /// Formats this value, where `printing` holds the lists and maps it is inside of.
+    /// 
+    /// A list or map inside of itself is printed as `[...]` or `{...}`, so lists and maps that contain themselves can be printed.
+    fn fmt_nested(&self, f: &mut fmt::Formatter<'_>, printing: &mut Vec<*const ()>) -> fmt::Result {
+        match self {
+            RuntimeVal::Str(s) => write!(f, "{s}"),
+            RuntimeVal::Num(n) => write!(f, "{n}"),
+            RuntimeVal::Bool(b) => write!(f, "{b}"),
+            RuntimeVal::Range(start, end) => write!(f, "{start}..{end}"),
+            RuntimeVal::List(items) => {
+                let ptr = Rc::as_ptr(items).cast();
+                if printing.contains(&ptr) {
+                    return write!(f, "[...]");
+                }
+
+                printing.push(ptr);
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    // Quote strings so they can be told apart from other values
+                    match item {
+                        RuntimeVal::Str(s) => write!(f, "{s:?}")?,
+                        _ => item.fmt_nested(f, printing)?
+                    }
+                }
+                printing.pop();
+                write!(f, "]")
+            }
+            RuntimeVal::Map(map) => {
+                let ptr = Rc::as_ptr(map).cast();
+                if printing.contains(&ptr) {
+                    return write!(f, "{{...}}");
+                }
+
+                printing.push(ptr);
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    // Quote strings so they can be told apart from other values
+                    match value {
+                        RuntimeVal::Str(s) => write!(f, "{key:?}: {s:?}")?,
+                        _ => {
+                            write!(f, "{key:?}: ")?;
+                            value.fmt_nested(f, printing)?
+                        }
+                    }
+                }
+                printing.pop();
+                write!(f, "}}")
+            }
+            RuntimeVal::Function(func) => write!(f, "<fn {}>", func.decl.name),
+            RuntimeVal::Native(func) => write!(f, "<fn {}>", func.name),
+            RuntimeVal::Null => write!(f, "null")
+        }
+    }
+}
+
+impl PartialEq for RuntimeVal {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_nested(other, &mut Vec::new())
+    }
+}
+
+impl fmt::Display for RuntimeVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_nested(f, &mut Vec::new())
+    }
+}
+
+/// An iterator over the items of a runtime value, used by `for` loops.
+/// 
+/// Each item is a key and a value. Keys are indices for lists and strings, and the number itself for ranges.
+#[derive(Clone, Debug)]
+pub enum RuntimeIter {
+    /// The remaining numbers of a range.
+    Range { next: f64, end: f64 },
+    /// The remaining items of a list or characters of a string, along with the index of the next one.
+    Items { next: usize, items: std::vec::IntoIter<RuntimeVal> },
+    /// The remaining keys and values of a map.
+    Entries(std::vec::IntoIter<(String, RuntimeVal)>)
+}
+
+impl RuntimeIter {
+    /// Constructs an iterator over the items of `val`, failing if it can't be iterated over.
+    /// 
+    /// Lists and maps are copied first, so changing them while iterating doesn't affect the iterator.
+    pub fn new(val: &RuntimeVal) -> anyhow::Result<Self> {
+        Ok(match val {
+            RuntimeVal::Range(start, end) => Self::Range { next: *start, end: *end },
+            RuntimeVal::List(items) => Self::Items { next: 0, items: items.borrow().clone().into_iter() },
+            RuntimeVal::Str(s) => Self::Items {
+                next: 0,
+                items: s.chars().map(|c| RuntimeVal::Str(c.to_string())).collect::<Vec<_>>().into_iter()
+            },
+            RuntimeVal::Map(map) => Self::Entries(
+                map.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect::<Vec<_>>().into_iter()
+            ),
+            _ => return Err(anyhow!("only ranges, lists, maps, and strings can be iterated over, but tried to iterate over {val}"))
+        })
+    }
+
+    /// Returns true if a loop binding a single name to each item should bind the key instead of the value.
+    pub fn binds_keys(&self) -> bool {
+        matches!(self, Self::Entries(_))
+    }
+}
+
+impl Iterator for RuntimeIter {
+    type Item = (RuntimeVal, RuntimeVal);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Range { next, end } => {
+                if *next >= *end {
+                    return None;
+                }
+
+                let n = *next;
+                *next += 1.0;
+                Some((RuntimeVal::Num(n), RuntimeVal::Num(n)))
+            }
+            Self::Items { next, items } => {
+                let item = items.next()?;
+                let index = *next;
+                *next += 1;
+                Some((RuntimeVal::Num(index as f64), item))
+            }
+            Self::Entries(entries) => entries.next().map(|(k, v)| (RuntimeVal::Str(k), v))
+        }
+    }
+}
+
+/// A runtime map of string keys to values, kept in the order keys were first inserted.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeMap {
+    entries: Vec<(String, RuntimeVal)>
+}
+
+impl RuntimeMap {
+    /// Returns the value of `key`.
+    pub fn get(&self, key: &str) -> Option<&RuntimeVal> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Sets the value of `key`, keeping its position if it is already in the map.
+    pub fn insert(&mut self, key: String, value: RuntimeVal) {
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => self.entries.push((key, value))
+        }
+    }
+
+    /// Returns the number of keys in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the map has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the keys and values of the map, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &RuntimeVal)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    /// Returns true if this map equals `other`, where `comparing` holds the pairs of lists and maps already being compared.
+    fn eq_nested(&self, other: &Self, comparing: &mut Vec<(*const (), *const ())>) -> bool {
+        // Maps are equal if they have the same keys and values, in any order
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k).is_some_and(|r| v.eq_nested(r, comparing)))
+    }
+}
+
+impl PartialEq for RuntimeMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_nested(other, &mut Vec::new())
+    }
+}
+
+/// A runtime function, along with the environment it was declared in.
+#[derive(Clone)]
+pub struct FunctionVal {
+    /// The declaration of this function.
+    pub decl: FnStmt,
+    /// The environment this function was declared in, or `None` if declared in the global environment.
+    /// 
+    /// The environment is held weakly, since it holds this function and would otherwise never be dropped.
+    pub env: Option<Weak<RuntimeEnv>>
+}
+
+impl fmt::Debug for FunctionVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Environment is left out, since it can contain this function
+        f.debug_struct("FunctionVal").field("decl", &self.decl).finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for FunctionVal {
+    fn eq(&self, other: &Self) -> bool {
+        let same_env = match (&self.env, &other.env) {
+            (Some(l), Some(r)) => Weak::ptr_eq(l, r),
+            (None, None) => true,
+            _ => false
+        };
+
+        self.decl == other.decl && same_env
+    }
+}
+
+/// The Rust function behind a native function, which takes already evaluated args.
+pub type NativeFnPtr = Rc<dyn Fn(Vec<RuntimeVal>) -> anyhow::Result<RuntimeVal>>;
+
+/// A function implemented in Rust, which code calls the same way as any other function.
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: String,
+    pub func: NativeFnPtr
+}
+
+impl NativeFn {
+    /// Constructs a new native function.
+    pub fn new(name: &str, func: impl Fn(Vec<RuntimeVal>) -> anyhow::Result<RuntimeVal> + 'static) -> Self {
+        Self { name: name.to_string(), func: Rc::new(func) }
+    }
+
+    /// Calls the function with already evaluated `args`.
+    pub fn call(&self, args: Vec<RuntimeVal>) -> anyhow::Result<RuntimeVal> {
+        (self.func)(args)
+    }
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFn").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+/// A control flow signal that unwinds the statements enclosing the statement that raised it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlFlow {
+    /// Exits the innermost loop.
+    Break,
+    /// Skips to the next iteration of the innermost loop.
+    Continue,
+    /// Exits the current function, returning a value.
+    Return(RuntimeVal)
+}
+
 /// A runtime environment containing items in current scope.
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct RuntimeEnv {
     /// The optional parent of this environment
-    pub parent: Option<Box<RuntimeEnv>>,
+    pub parent: Option<Rc<RuntimeEnv>>,
     /// The symbols in an environment, each containing a runtime value.
-    pub symbols: RefCell<HashMap<String, RuntimeVal>>
+    pub symbols: RefCell<HashMap<String, RuntimeVal>>,
+    /// The names of the symbols in this environment that can't be reassigned.
+    pub constants: RefCell<HashSet<String>>
 }
 
 impl RuntimeEnv {
     /// Constructs a new runtime environment with an optional parent and symbols.
-    pub fn new(parent: Option<Box<RuntimeEnv>>, symbols: RefCell<HashMap<String, RuntimeVal>>) -> Self {
-        Self { parent, symbols }
+    pub fn new(parent: Option<Rc<RuntimeEnv>>, symbols: RefCell<HashMap<String, RuntimeVal>>) -> Self {
+        Self { parent, symbols, constants: RefCell::default() }
     }
 
     /// Constructs a new runtime environment with a parent and no vars.
-    pub fn create_with_parent(parent: Box<RuntimeEnv>) -> Self {
-        Self { parent: Some(parent), symbols: RefCell::default() }
+    pub fn create_with_parent(parent: Rc<RuntimeEnv>) -> Self {
+        Self { parent: Some(parent), symbols: RefCell::default(), constants: RefCell::default() }
+    }
+
+    /// Returns the outermost environment in this environment's parent chain.
+    pub fn global(self: &Rc<Self>) -> Rc<Self> {
+        match &self.parent {
+            Some(parent) => parent.global(),
+            None => self.clone()
+        }
     }
 
-    /// Returns the value of a variable with `name`.
+    /// Returns the value of a variable with `name`, searching parent environments if it isn't in this one.
     pub fn get_var(&self, name: &str) -> Option<RuntimeVal> {
-        if self.parent.is_none() {
-            self.symbols.borrow().get(name).and_then(|s| Some((*s).clone()))
-        } else {
-            panic!("Getting variable from parent env is not yet implemented!")
+        match self.symbols.borrow().get(name) {
+            Some(s) => Some(s.clone()),
+            None => self.parent.as_ref().and_then(|p| p.get_var(name))
         }
     }
     
@@ -47,4 +367,170 @@ impl RuntimeEnv {
     pub fn var_exists(&self, name: &str) -> bool {
         self.symbols.borrow().contains_key(name)
     }
+
+    /// Returns the nearest environment in the parent chain that defines a var with `name`.
+    pub fn find_var_env(&self, name: &str) -> Option<&RuntimeEnv> {
+        if self.var_exists(name) {
+            Some(self)
+        } else {
+            self.parent.as_ref().and_then(|p| p.find_var_env(name))
+        }
+    }
+
+    /// Declares a var with `name` in this env, shadowing any var with `name` in parent environments.
+    /// 
+    /// Fails if a constant with `name` is already declared in this env.
+    pub fn declare_var(&self, name: &str, value: RuntimeVal) -> anyhow::Result<()> {
+        if self.constants.borrow().contains(name) {
+            return Err(anyhow!("cannot redeclare constant '{name}'"));
+        }
+
+        self.symbols.borrow_mut().insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Declares a constant with `name` in this env, which can't be reassigned.
+    pub fn declare_const(&self, name: &str, value: RuntimeVal) -> anyhow::Result<()> {
+        self.declare_var(name, value)?;
+        self.constants.borrow_mut().insert(name.to_string());
+        Ok(())
+    }
+
+    /// Assigns `value` to the var with `name` in the nearest environment defining it.
+    /// 
+    /// Fails if the var isn't declared or is a constant.
+    pub fn assign_var(&self, name: &str, value: RuntimeVal) -> anyhow::Result<()> {
+        match self.find_var_env(name) {
+            Some(env) if env.constants.borrow().contains(name) => {
+                Err(anyhow!("cannot assign to constant '{name}'"))
+            }
+            Some(env) => {
+                env.symbols.borrow_mut().insert(name.to_string(), value);
+                Ok(())
+            }
+            None => Err(anyhow!("cannot assign to undeclared var '{name}', declare it with 'let' first"))
+        }
+    }
+}
+
+/// Implements `From` for runtime values of types that convert to one variant.
+macro_rules! impl_from_for_runtime_val {
+    ($($ty:ty => |$v:ident| $val:expr),* $(,)?) => {
+        $(impl From<$ty> for RuntimeVal {
+            fn from($v: $ty) -> Self {
+                $val
+            }
+        })*
+    };
+}
+
+impl_from_for_runtime_val! {
+    f64 => |n| RuntimeVal::Num(n),
+    i32 => |n| RuntimeVal::Num(n.into()),
+    i64 => |n| RuntimeVal::Num(n as f64),
+    usize => |n| RuntimeVal::Num(n as f64),
+    bool => |b| RuntimeVal::Bool(b),
+    String => |s| RuntimeVal::Str(s),
+    &str => |s| RuntimeVal::Str(s.to_string()),
+    NativeFn => |f| RuntimeVal::Native(f)
+}
+
+impl<T: Into<RuntimeVal>> From<Vec<T>> for RuntimeVal {
+    fn from(items: Vec<T>) -> Self {
+        RuntimeVal::List(Rc::new(RefCell::new(items.into_iter().map(Into::into).collect())))
+    }
+}
+
+impl<T: Into<RuntimeVal>> From<HashMap<String, T>> for RuntimeVal {
+    fn from(entries: HashMap<String, T>) -> Self {
+        let mut map = RuntimeMap::default();
+        for (key, value) in entries {
+            map.insert(key, value.into());
+        }
+
+        RuntimeVal::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+impl<T: Into<RuntimeVal>> From<Option<T>> for RuntimeVal {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(RuntimeVal::Null, Into::into)
+    }
+}
+
+/// A Rust type that runtime values can be read back as.
+pub trait FromRuntimeVal: Sized {
+    /// Converts `val` to this type, failing if it holds a different type of value.
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self>;
+}
+
+impl FromRuntimeVal for RuntimeVal {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        Ok(val)
+    }
+}
+
+impl FromRuntimeVal for f64 {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        match val {
+            RuntimeVal::Num(n) => Ok(n),
+            val => Err(anyhow!("expected a number, but got {val}"))
+        }
+    }
+}
+
+impl FromRuntimeVal for i64 {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        match val {
+            RuntimeVal::Num(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(n as i64),
+            val => Err(anyhow!("expected a whole number, but got {val}"))
+        }
+    }
+}
+
+impl FromRuntimeVal for bool {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        match val {
+            RuntimeVal::Bool(b) => Ok(b),
+            val => Err(anyhow!("expected a bool, but got {val}"))
+        }
+    }
+}
+
+impl FromRuntimeVal for String {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        match val {
+            RuntimeVal::Str(s) => Ok(s),
+            val => Err(anyhow!("expected a string, but got {val}"))
+        }
+    }
+}
+
+impl<T: FromRuntimeVal> FromRuntimeVal for Vec<T> {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        match val {
+            RuntimeVal::List(items) => items.borrow().iter().cloned().map(T::from_runtime_val).collect(),
+            val => Err(anyhow!("expected a list, but got {val}"))
+        }
+    }
+}
+
+impl<T: FromRuntimeVal> FromRuntimeVal for HashMap<String, T> {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        match val {
+            RuntimeVal::Map(map) => map.borrow().iter()
+                .map(|(key, value)| Ok((key.clone(), T::from_runtime_val(value.clone())?)))
+                .collect(),
+            val => Err(anyhow!("expected a map, but got {val}"))
+        }
+    }
+}
+
+impl<T: FromRuntimeVal> FromRuntimeVal for Option<T> {
+    fn from_runtime_val(val: RuntimeVal) -> anyhow::Result<Self> {
+        match val {
+            RuntimeVal::Null => Ok(None),
+            val => T::from_runtime_val(val).map(Some)
+        }
+    }
 }
@@ -0,0 +1,332 @@
+/*!
+    Contains things related to running shell commands.
+*/
+
+use std::{
+    cell::RefCell,
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    rc::Rc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant}
+};
+use anyhow::anyhow;
+use crate::runtime::{RuntimeMap, RuntimeVal};
+
+/// The output of a command that finished running.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// The exit code of the command, or -1 if it was stopped by a signal.
+    pub code: i32
+}
+
+impl CommandOutput {
+    /// Returns true if the command exited with a code of 0.
+    pub fn success(&self) -> bool {
+        self.code == 0
+    }
+
+    /// Returns an error describing the command exiting with a non-zero code.
+    ///
+    /// `command` is how the command is shown in the error.
+    pub fn failure_error(&self, command: &str) -> anyhow::Error {
+        match self.stderr.trim() {
+            "" => anyhow!("command '{command}' exited with code {}", self.code),
+            stderr => anyhow!("command '{command}' exited with code {}: {stderr}", self.code)
+        }
+    }
+
+    /// Returns the output as a map holding its `stdout`, `stderr` and exit `code`.
+    pub fn to_runtime_val(&self) -> RuntimeVal {
+        let mut map = RuntimeMap::default();
+        map.insert("stdout".to_string(), RuntimeVal::Str(self.stdout.clone()));
+        map.insert("stderr".to_string(), RuntimeVal::Str(self.stderr.clone()));
+        map.insert("code".to_string(), RuntimeVal::Num(self.code as f64));
+
+        RuntimeVal::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+/// Where the output of a command goes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Redirect {
+    /// Captures the output so it is returned.
+    #[default]
+    Capture,
+    /// Writes the output to a file, replacing what it held.
+    File(PathBuf),
+    /// Writes the output to the end of a file.
+    Append(PathBuf)
+}
+
+impl Redirect {
+    /// Opens the file the output goes to, if it doesn't get captured.
+    fn open(&self) -> anyhow::Result<Option<File>> {
+        let (path, file) = match self {
+            Self::Capture => return Ok(None),
+            Self::File(path) => (path, File::create(path)),
+            Self::Append(path) => (path, OpenOptions::new().create(true).append(true).open(path))
+        };
+
+        file.map(Some).map_err(|e| anyhow!("couldn't open '{}' for output: {e}", path.display()))
+    }
+}
+
+/// Settings for how commands are run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunOptions {
+    /// Text written to the standard input of the command, which otherwise reads nothing.
+    pub stdin: Option<String>,
+    /// The directory the command runs in, instead of the current one.
+    pub cwd: Option<PathBuf>,
+    /// Environment variables set for the command, on top of the inherited ones.
+    pub env: Vec<(String, String)>,
+    /// How long the command can run before it is killed.
+    pub timeout: Option<Duration>,
+    pub stdout: Redirect,
+    pub stderr: Redirect
+}
+
+/// A program to run along with its args.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Program {
+    pub name: String,
+    pub args: Vec<String>
+}
+
+impl Program {
+    /// Constructs a new program.
+    pub fn new(name: impl Into<String>, args: Vec<String>) -> Self {
+        Self { name: name.into(), args }
+    }
+}
+
+/// Runs `code` with the system shell, capturing its output.
+pub fn run_shell(code: &str, options: &RunOptions) -> anyhow::Result<CommandOutput> {
+    run_pipeline(&[Program::new("sh", vec!["-c".to_string(), code.to_string()])], options)
+}
+
+/// Runs `program` directly with `args`, without a shell, capturing its output.
+pub fn run_program(program: &str, args: &[String], options: &RunOptions) -> anyhow::Result<CommandOutput> {
+    run_pipeline(&[Program::new(program, args.to_vec())], options)
+}
+
+/// Runs `programs` at the same time, with the standard output of each one going to the standard input of the next.
+///
+/// Options apply to the whole pipeline, with `stdin` going to the first program and `stdout` coming from the last one.
+/// The standard error of every program is collected, and the exit code is the one of the last program.
+pub fn run_pipeline(programs: &[Program], options: &RunOptions) -> anyhow::Result<CommandOutput> {
+    if programs.is_empty() {
+        return Err(anyhow!("a pipeline needs at least one program"));
+    }
+    let stdout_file = options.stdout.open()?;
+    let stderr_file = options.stderr.open()?;
+
+    let mut children: Vec<Child> = Vec::new();
+    let mut stderr_readers = Vec::new();
+    let mut prev_stdout = None;
+    for (i, program) in programs.iter().enumerate() {
+        let is_last = i == programs.len() - 1;
+
+        let mut command = Command::new(&program.name);
+        command.args(&program.args).envs(options.env.iter().map(|(k, v)| (k, v)));
+        if let Some(cwd) = &options.cwd {
+            command.current_dir(cwd);
+        }
+
+        command.stdin(match prev_stdout.take() {
+            Some(stdout) => Stdio::from(stdout),
+            None if options.stdin.is_some() => Stdio::piped(),
+            None => Stdio::null()
+        });
+        command.stdout(match &stdout_file {
+            Some(file) if is_last => file.try_clone()?.into(),
+            _ => Stdio::piped()
+        });
+        command.stderr(match &stderr_file {
+            Some(file) => file.try_clone()?.into(),
+            None => Stdio::piped()
+        });
+
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                kill_all(&mut children);
+                return Err(anyhow!("couldn't run program '{}': {e}", program.name));
+            }
+        };
+        if !is_last {
+            prev_stdout = child.stdout.take();
+        }
+        stderr_readers.extend(child.stderr.take().map(read_in_background));
+        children.push(child);
+    }
+
+    // Write input and read output in the background, so no program gets stuck on a full pipe
+    if let (Some(mut pipe), Some(text)) = (children[0].stdin.take(), options.stdin.clone()) {
+        thread::spawn(move || pipe.write_all(text.as_bytes()));
+    }
+    let stdout_reader = children.last_mut().and_then(|c| c.stdout.take()).map(read_in_background);
+
+    let statuses = wait_all(&mut children, options.timeout)?;
+
+    let join = |reader: JoinHandle<String>| reader.join().unwrap_or_default();
+    Ok(CommandOutput {
+        stdout: stdout_reader.map(join).unwrap_or_default(),
+        stderr: stderr_readers.into_iter().map(join).collect(),
+        code: statuses.last().and_then(|s| s.code()).unwrap_or(-1)
+    })
+}
+
+/// Reads everything from `reader` on another thread.
+fn read_in_background(mut reader: impl Read + Send + 'static) -> JoinHandle<String> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        _ = reader.read_to_end(&mut bytes);
+        String::from_utf8_lossy(&bytes).into_owned()
+    })
+}
+
+/// Waits for every child to exit, killing all of them if they take longer than `timeout`.
+fn wait_all(children: &mut [Child], timeout: Option<Duration>) -> anyhow::Result<Vec<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return children.iter_mut().map(|c| c.wait().map_err(anyhow::Error::from)).collect();
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut statuses = vec![None; children.len()];
+    loop {
+        for (child, status) in children.iter_mut().zip(statuses.iter_mut()) {
+            if status.is_none() {
+                *status = child.try_wait()?;
+            }
+        }
+        if statuses.iter().all(Option::is_some) {
+            return Ok(statuses.into_iter().flatten().collect());
+        }
+
+        if Instant::now() >= deadline {
+            kill_all(children);
+            return Err(anyhow!("command timed out after {}s", timeout.as_secs_f64()));
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// Kills every child and waits for them to exit.
+fn kill_all(children: &mut [Child]) {
+    for child in children {
+        _ = child.kill();
+        _ = child.wait();
+    }
+}
+
+/// Quotes `s` so the shell reads it as a single word, with no special characters.
+pub fn quote(s: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_-+=:,./@%".contains(c);
+    if !s.is_empty() && s.chars().all(is_safe) {
+        return s.to_string();
+    }
+
+    // Nothing is special inside of single quotes, so only single quotes need escaping
+    format!("'{}'", s.replace('\'', r#"'\''"#))
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+
+    #[test]
+    fn test_run_shell() {
+        let output = run_shell("echo out; echo err >&2; exit 3", &RunOptions::default()).unwrap();
+
+        assert_eq!(output, CommandOutput {
+            stdout: "out\n".to_string(),
+            stderr: "err\n".to_string(),
+            code: 3
+        });
+        assert!(!output.success());
+        assert_eq!(output.failure_error("x").to_string(), "command 'x' exited with code 3: err");
+    }
+
+    #[test]
+    fn test_run_program() {
+        let output = run_program("printf", &["%s|".to_string(), "a b".to_string(), "$HOME".to_string()], &RunOptions::default()).unwrap();
+        assert_eq!(output.stdout, "a b|$HOME|");
+
+        assert!(run_program("seel-missing-program", &[], &RunOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("file.txt"), "file.txt");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b; rm -rf ~"), "'a b; rm -rf ~'");
+        assert_eq!(quote("it's $x"), r#"'it'\''s $x'"#);
+
+        // Quoted values reach commands unchanged
+        let output = run_shell(&format!("printf %s {}", quote("it's `$x` \\n")), &RunOptions::default()).unwrap();
+        assert_eq!(output.stdout, "it's `$x` \\n");
+    }
+
+    #[test]
+    fn test_run_pipeline() {
+        let programs = [
+            Program::new("printf", vec!["b\na\nb\nc\n".to_string()]),
+            Program::new("sort", vec!["-u".to_string()]),
+            Program::new("sh", vec!["-c".to_string(), "cat; echo done >&2; exit 4".to_string()])
+        ];
+        let output = run_pipeline(&programs, &RunOptions::default()).unwrap();
+
+        assert_eq!(output, CommandOutput {
+            stdout: "a\nb\nc\n".to_string(),
+            stderr: "done\n".to_string(),
+            code: 4
+        });
+
+        assert!(run_pipeline(&[], &RunOptions::default()).is_err());
+        assert!(run_pipeline(&[programs[0].clone(), Program::new("seel-missing-program", vec![])], &RunOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_run_options() {
+        let dir = std::env::temp_dir().join(format!("seel-test-run-options-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let options = RunOptions {
+            stdin: Some("input".to_string()),
+            cwd: Some(dir.clone()),
+            env: vec![("SEEL_TEST".to_string(), "value".to_string())],
+            ..Default::default()
+        };
+        let output = run_shell("cat; echo \" $SEEL_TEST\"; pwd", &options).unwrap();
+        let cwd = dir.canonicalize().unwrap();
+        assert_eq!(output.stdout, format!("input value\n{}\n", cwd.display()));
+
+        // Redirected output isn't captured
+        let log = dir.join("log.txt");
+        let output = run_shell("echo one", &RunOptions { stdout: Redirect::File(log.clone()), ..Default::default() }).unwrap();
+        run_shell("echo two >&2", &RunOptions { stderr: Redirect::Append(log.clone()), ..Default::default() }).unwrap();
+        assert_eq!(output.stdout, "");
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "one\ntwo\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let options = RunOptions { timeout: Some(Duration::from_millis(100)), ..Default::default() };
+
+        let start = Instant::now();
+        let error = run_program("sleep", &["5".to_string()], &options).unwrap_err();
+        assert_eq!(error.to_string(), "command timed out after 0.1s");
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        assert!(run_program("true", &[], &options).is_ok());
+    }
+}
@@ -1,10 +1,21 @@
 /*!
     Contains `Visitor` trait and structs that implement `Visitor` trait.
 */
-use std::{ffi::CString, io::prelude::*};
+use std::{cell::{Cell, RefCell}, ffi::CString, rc::Rc, time::{Duration, Instant}};
 use anyhow::anyhow;
-use frontend::parser::{CompareOp, Expr, Op, Stmt};
-use crate::runtime::{RuntimeEnv, RuntimeVal};
+use frontend::{diagnostics::{Diagnostic, Span}, parser::{CompareOp, Expr, ExprKind, InterpPart, LogicalOp, Op, Stmt, StmtKind}};
+use crate::builtins::{arg_to_string, call_builtin, is_builtin};
+use crate::console::{Console, StdConsole};
+use crate::limits::{LimitError, Limits};
+use crate::permissions::{Capability, Permissions, SHELL_PROGRAM};
+use crate::shell::{quote, run_shell, RunOptions};
+use crate::runtime::{ControlFlow, FunctionVal, RuntimeEnv, RuntimeIter, RuntimeMap, RuntimeVal};
+
+/// The furthest from 0 a range bound can be, past which adding 1 to a number can leave it unchanged.
+const MAX_RANGE_BOUND: f64 = 9_007_199_254_740_992.0;
+
+/// The stack that must be left to evaluate a statement or expression, which leaves room for builtins and formatting values.
+const MIN_STACK_LEFT: usize = 128 * 1024;
 
 /// Macros that `Visitor` trait uses.
 mod visitor_macros {
@@ -58,20 +69,40 @@ pub trait Visitor {
         // Statements
         Stmt => visit_stmt,
         Stmt => visit_stmt_if,
+        Stmt => visit_stmt_while,
+        Stmt => visit_stmt_for,
+        Stmt => visit_stmt_break,
+        Stmt => visit_stmt_continue,
         Stmt => visit_stmt_block,
         Stmt => visit_stmt_print,
+        Stmt => visit_stmt_let,
+        Stmt => visit_stmt_const,
         Stmt => visit_stmt_assign,
+        Stmt => visit_stmt_index_assign,
+        Stmt => visit_stmt_fn,
+        Stmt => visit_stmt_return,
         Stmt => visit_bash_code_stmt,
 
         // Expressions
-        Box<Expr> => visit_expr,
-        Box<Expr> => visit_compare_expr,
-        Box<Expr> => visit_read_expr,
-        Box<Expr> => visit_binary_expr,
-        Box<Expr> => visit_bool_expr,
-        Box<Expr> => visit_num_expr,
-        Box<Expr> => visit_str_expr,
-        Box<Expr> => visit_ident_expr
+        Expr => visit_expr,
+        Expr => visit_compare_expr,
+        Expr => visit_logical_expr,
+        Expr => visit_not_expr,
+        Expr => visit_read_expr,
+        Expr => visit_binary_expr,
+        Expr => visit_neg_expr,
+        Expr => visit_bool_expr,
+        Expr => visit_num_expr,
+        Expr => visit_str_expr,
+        Expr => visit_interp_expr,
+        Expr => visit_shell_expr,
+        Expr => visit_ident_expr,
+        Expr => visit_call_expr,
+        Expr => visit_range_expr,
+        Expr => visit_list_expr,
+        Expr => visit_map_expr,
+        Expr => visit_index_expr,
+        Expr => visit_slice_expr
     }
 }
 
@@ -79,46 +110,357 @@ pub trait Visitor {
 use visitor_macros::with_extract_enum_variant;
 
 /// General visitor.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug)]
 pub struct GeneralVisitor {
-    env: Box<RuntimeEnv>
+    /// The environment of the scope currently being evaluated.
+    env: RefCell<Rc<RuntimeEnv>>,
+    /// The control flow signal currently unwinding, if any.
+    control_flow: RefCell<Option<ControlFlow>>,
+    /// The number of loops currently being evaluated in the current function call.
+    loop_depth: Cell<usize>,
+    /// The number of function calls currently being evaluated.
+    call_depth: Cell<usize>,
+    /// What evaluated code is allowed to do outside of the interpreter.
+    permissions: Permissions,
+    /// How much work evaluated code is allowed to do.
+    limits: Limits,
+    /// The number of statements and expressions evaluated so far.
+    steps: Cell<u64>,
+    /// When the visitor was created, which the timeout counts from.
+    started: Instant,
+    /// Where `print` writes to and `read` reads from.
+    console: Rc<dyn Console>
 }
 
 impl GeneralVisitor {
     /// Return a new general visitor, given a runtime environment.
-    pub fn new(env: Box<RuntimeEnv>) -> Self {
-        Self { env }
+    pub fn new(env: Rc<RuntimeEnv>) -> Self {
+        Self {
+            env: RefCell::new(env),
+            control_flow: RefCell::default(),
+            loop_depth: Cell::default(),
+            call_depth: Cell::default(),
+            permissions: Permissions::default(),
+            limits: Limits::default(),
+            steps: Cell::default(),
+            started: Instant::now(),
+            console: Rc::new(StdConsole)
+        }
+    }
+
+    /// Returns this visitor with `print` and `read` using `console`.
+    pub fn with_console(mut self, console: Rc<dyn Console>) -> Self {
+        self.console = console;
+        self
+    }
+
+    /// Returns this visitor with code only allowed to do as much work as `limits` allow.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Returns this visitor with code only allowed to do what `permissions` allow.
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Returns the environment of the scope currently being evaluated.
+    fn env(&self) -> Rc<RuntimeEnv> {
+        self.env.borrow().clone()
+    }
+
+    /// Evaluates `stmts`, returning the value of the last expression statement evaluated, or null if there wasn't one.
+    pub fn eval_program(&self, stmts: &[Stmt]) -> anyhow::Result<RuntimeVal> {
+        let mut value = RuntimeVal::Null;
+        for stmt in stmts {
+            let result = self.visit_stmt(stmt)?;
+            if matches!(stmt.kind, StmtKind::Expr(_)) {
+                value = result;
+            }
+
+            // Stop evaluating if a control flow signal was raised
+            if self.control_flow.borrow().is_some() {
+                break;
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Counts a statement or expression being evaluated, failing if that goes over the step limit or timeout,
+    /// or if there isn't enough stack left to evaluate it.
+    fn step(&self) -> anyhow::Result<()> {
+        // Evaluating nested code recurses, so stop before the stack overflows, however big it is
+        if stacker::remaining_stack().is_some_and(|left| left < MIN_STACK_LEFT) {
+            return Err(LimitError::Stack.into());
+        }
+
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+
+        if let Some(max) = self.limits.max_steps.filter(|max| steps > *max) {
+            return Err(LimitError::Steps(max).into());
+        }
+
+        // Checking the time is slower than counting, so only do it every so often
+        if steps.is_multiple_of(64) {
+            self.time_left()?;
+        }
+
+        // Return no errors
+        Ok(())
+    }
+
+    /// Returns how much longer code can run before going over the timeout, failing if it already has.
+    fn time_left(&self) -> anyhow::Result<Option<Duration>> {
+        let Some(timeout) = self.limits.timeout else {
+            return Ok(None);
+        };
+
+        match timeout.checked_sub(self.started.elapsed()).filter(|left| !left.is_zero()) {
+            Some(left) => Ok(Some(left)),
+            None => Err(LimitError::Timeout(timeout).into())
+        }
+    }
+
+    /// Runs `command`, which can wait on something outside of the interpreter, passing it the time left before the timeout.
+    ///
+    /// Fails with a timeout error if the timeout passed while it ran, whether or not it finished.
+    fn with_time_left<T>(&self, command: impl FnOnce(Option<Duration>) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let result = command(self.time_left()?);
+        self.time_left()?;
+        result
+    }
+
+    /// Fails if a list or map holding `len` items would go over the collection size limit.
+    fn check_collection_len(&self, len: usize) -> anyhow::Result<()> {
+        match self.limits.max_collection_len.filter(|max| len > *max) {
+            Some(max) => Err(LimitError::CollectionLen(max).into()),
+            None => Ok(())
+        }
+    }
+
+    /// Evaluates `code` in a new scope that is dropped afterwards.
+    fn with_scope<T>(&self, code: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        // Push new child scope
+        let scope = RuntimeEnv::create_with_parent(self.env());
+        let parent_env = self.env.replace(Rc::new(scope));
+
+        let result = code();
+
+        // Pop child scope
+        self.env.replace(parent_env);
+        result
+    }
+
+    /// Evaluates `expr` as a condition, failing if it doesn't evaluate to a bool.
+    fn eval_condition(&self, expr: &Expr) -> anyhow::Result<bool> {
+        match self.visit_expr(expr)? {
+            RuntimeVal::Bool(b) => Ok(b),
+            val => Err(at_span(anyhow!("condition must evaluate to a bool, but evaluated to {val}"), expr.span))
+        }
+    }
+
+    /// Evaluates `expr` as a list, failing if it doesn't evaluate to a list.
+    fn eval_list(&self, expr: &Expr) -> anyhow::Result<Rc<RefCell<Vec<RuntimeVal>>>> {
+        match self.visit_expr(expr)? {
+            RuntimeVal::List(items) => Ok(items),
+            val => Err(anyhow!("only lists can be sliced, but tried to slice {val}"))
+        }
+    }
+
+    /// Evaluates `expr` as a key of a map, failing if it doesn't evaluate to a string.
+    fn eval_map_key(&self, expr: &Expr) -> anyhow::Result<String> {
+        match self.visit_expr(expr)? {
+            RuntimeVal::Str(key) => Ok(key),
+            val => Err(anyhow!("map keys must be strings, but got {val}"))
+        }
+    }
+
+    /// Evaluates `expr` as a bound of a range, failing if it isn't a whole number that counting up from stays exact.
+    fn eval_range_bound(&self, expr: &Expr) -> anyhow::Result<f64> {
+        let error = match self.visit_expr(expr)? {
+            RuntimeVal::Num(n) if n.fract() == 0.0 && n.abs() <= MAX_RANGE_BOUND => return Ok(n),
+            RuntimeVal::Num(n) if n.fract() == 0.0 => anyhow!("range bounds can't be further from 0 than 2^53, but got {n}"),
+            RuntimeVal::Num(n) => anyhow!("range bounds must be whole numbers, but got {n}"),
+            val => anyhow!("range bounds must be numbers, but got {val}")
+        };
+
+        Err(at_span(error, expr.span))
+    }
+
+    /// Evaluates `expr` as a position in a list of length `len`.
+    /// 
+    /// If `allow_end` is true, the position just past the last item is allowed (eg. for slices).
+    fn eval_list_position(&self, expr: &Expr, len: usize, allow_end: bool) -> anyhow::Result<usize> {
+        with_extract_enum_variant!(self.visit_expr(expr)?, RuntimeVal::Num(index), {
+            get_list_position(index, len, allow_end)
+        })
+    }
+
+    /// Calls `function` with already evaluated `args`, returning the value it returns.
+    fn call_function(&self, function: &FunctionVal, args: Vec<RuntimeVal>) -> anyhow::Result<RuntimeVal> {
+        let decl = &function.decl;
+        if let Some(max) = self.limits.max_call_depth.filter(|max| self.call_depth.get() >= *max) {
+            return Err(LimitError::CallDepth(max).into());
+        }
+        if decl.params.len() != args.len() {
+            return Err(anyhow!(
+                "function '{}' takes {} argument(s) but {} were given",
+                decl.name, decl.params.len(), args.len()
+            ));
+        }
+
+        // Create call frame inside the function's environment, with args bound to params
+        let frame = RuntimeEnv::create_with_parent(match &function.env {
+            Some(env) => env.upgrade().ok_or_else(|| anyhow!(
+                "function '{}' can't be called once the scope it was declared in has ended", decl.name
+            ))?,
+            None => self.env().global()
+        });
+        for (param, arg) in decl.params.iter().zip(args) {
+            frame.declare_var(param, arg)?;
+        }
+
+        // Evaluate function body in call frame, with no enclosing loops
+        let caller_env = self.env.replace(Rc::new(frame));
+        let caller_loop_depth = self.loop_depth.replace(0);
+        self.call_depth.set(self.call_depth.get() + 1);
+
+        let result = self.visit_program(&decl.code);
+
+        self.call_depth.set(self.call_depth.get() - 1);
+        self.loop_depth.set(caller_loop_depth);
+        self.env.replace(caller_env);
+        result?;
+
+        // Return the returned value, or null if nothing was returned
+        match self.control_flow.take() {
+            Some(ControlFlow::Return(val)) => Ok(val),
+            _ => Ok(RuntimeVal::Null)
+        }
+    }
+
+    /// Raises a loop control flow signal, failing if no loop is being evaluated.
+    fn raise_loop_signal(&self, signal: ControlFlow, keyword: &str) -> anyhow::Result<RuntimeVal> {
+        if self.loop_depth.get() == 0 {
+            return Err(anyhow!("'{keyword}' can only be used inside of a loop"));
+        }
+
+        // Set signal so enclosing statements stop evaluating
+        *self.control_flow.borrow_mut() = Some(signal);
+
+        // Return null because this is a statement
+        Ok(RuntimeVal::Null)
+    }
+
+    /// Evaluates a loop, so `break` and `continue` know they are inside of one.
+    fn in_loop<T>(&self, code: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        self.loop_depth.set(self.loop_depth.get() + 1);
+        let result = code();
+        self.loop_depth.set(self.loop_depth.get() - 1);
+
+        result
+    }
+
+    /// Consumes any loop signal raised by a loop body, returning true if the loop should stop.
+    fn take_loop_signal(&self) -> bool {
+        match self.control_flow.take() {
+            Some(ControlFlow::Break) => true,
+            Some(ControlFlow::Return(val)) => {
+                // Let returns keep unwinding to the function call
+                *self.control_flow.borrow_mut() = Some(ControlFlow::Return(val));
+                true
+            }
+            Some(ControlFlow::Continue) | None => false
+        }
+    }
+
+    /// Runs `code` for as long as `comparison` evaluates to true.
+    fn run_while_loop(&self, comparison: &Expr, code: &[Stmt]) -> anyhow::Result<()> {
+        loop {
+            // Stop looping once comparison is false
+            if !self.eval_condition(comparison)? {
+                break;
+            }
+
+            self.with_scope(|| self.eval_program(code))?;
+            if self.take_loop_signal() {
+                break;
+            }
+        }
+
+        // Return no errors
+        Ok(())
+    }
+
+    /// Runs `code` once for every item of `iterable`, with each item bound to `names`.
+    fn run_for_loop(&self, names: &[String], iterable: &Expr, code: &[Stmt]) -> anyhow::Result<()> {
+        let iter = RuntimeIter::new(&self.visit_expr(iterable)?)?;
+        let binds_keys = iter.binds_keys();
+
+        for (key, value) in iter {
+            // Bind item in the scope of this iteration
+            let stop = self.with_scope(|| {
+                let env = self.env();
+                match names {
+                    [name] => env.declare_var(name, if binds_keys { key } else { value })?,
+                    [key_name, value_name] => {
+                        env.declare_var(key_name, key)?;
+                        env.declare_var(value_name, value)?;
+                    }
+                    _ => return Err(anyhow!("for loops bind either one or two names"))
+                }
+
+                self.eval_program(code)?;
+                Ok(self.take_loop_signal())
+            })?;
+
+            if stop {
+                break;
+            }
+        }
+
+        // Return no errors
+        Ok(())
+    }
+}
+
+/// Attaches `span` to `error`, unless the span of a more specific node is already attached.
+///
+/// The original error is kept underneath, so it can still be found by downcasting.
+fn at_span(error: anyhow::Error, span: Span) -> anyhow::Error {
+    if error.is::<Diagnostic>() {
+        error
+    } else {
+        let diagnostic = Diagnostic::new(error.to_string(), span);
+        error.context(diagnostic)
     }
 }
 
-/// Prints the value of a `RuntimeVal`.
-fn print_runtime_val(env: &RuntimeEnv, runtime_val: &RuntimeVal) {
-    // Print runtime value
-    match runtime_val {
-        RuntimeVal::Ident(name) => {
-            // Call function recursively to print value of ident
-            print_runtime_val(env,&env.get_var(&name).unwrap())
-        },
-        RuntimeVal::Bool(b) => println!("{}", b),
-        RuntimeVal::Str(s) => println!("{}", s),
-        RuntimeVal::Num(n) => println!("{}", n),
-        RuntimeVal::Null => println!("null")
-    };
+/// Returns the position in a list of length `len` that `index` refers to, counting from the end if negative.
+/// 
+/// If `allow_end` is true, the position just past the last item is allowed.
+fn get_list_position(index: f64, len: usize, allow_end: bool) -> anyhow::Result<usize> {
+    if index.fract() != 0.0 {
+        return Err(anyhow!("list index must be a whole number, but got {index}"));
+    }
+
+    // Count negative indices from the end
+    let position = if index < 0.0 { len as f64 + index } else { index };
+    let end = if allow_end { len as f64 } else { len as f64 - 1.0 };
+    if position < 0.0 || position > end {
+        return Err(anyhow!("list index {index} is out of range for list of length {len}"));
+    }
+
+    Ok(position as usize)
 }
 
 /// Returns the result of an equality equation (eg. "2 == 2").
-fn get_equality(env: &Box<RuntimeEnv>, lhs: RuntimeVal, op: CompareOp, rhs: RuntimeVal) -> anyhow::Result<bool> {
+fn get_equality(lhs: RuntimeVal, op: CompareOp, rhs: RuntimeVal) -> anyhow::Result<bool> {
     Ok(match lhs {
-        RuntimeVal::Ident(var) => {
-            // Get value of var with name `var`
-            let var_eval = match env.get_var(var.as_str()) {
-                Some(var_value) => var_value,
-                None => return Err(anyhow!("Cannot get value of var '{var}'"))
-            };
-
-            // Return equality between new taken var value and rhs
-            get_equality(env, var_eval, op, rhs)?
-        }
         RuntimeVal::Bool(lbool) => {
             // Can only compare between bool and bool
             with_extract_enum_variant!(rhs, RuntimeVal::Bool(rbool), {
@@ -152,7 +494,27 @@ fn get_equality(env: &Box<RuntimeEnv>, lhs: RuntimeVal, op: CompareOp, rhs: Runt
                 }
             })
         }
-        _ => return Err(anyhow!("only bool, num, and str supported in if condition"))
+        RuntimeVal::List(llist) => {
+            // Can only compare between list and list
+            with_extract_enum_variant!(rhs, RuntimeVal::List(rlist), {
+                match op {
+                    CompareOp::Equal => RuntimeVal::List(llist) == RuntimeVal::List(rlist),
+                    CompareOp::NEqual => RuntimeVal::List(llist) != RuntimeVal::List(rlist),
+                    _ => return Err(anyhow!("only == and != supported between two list values"))
+                }
+            })
+        }
+        RuntimeVal::Map(lmap) => {
+            // Can only compare between map and map
+            with_extract_enum_variant!(rhs, RuntimeVal::Map(rmap), {
+                match op {
+                    CompareOp::Equal => RuntimeVal::Map(lmap) == RuntimeVal::Map(rmap),
+                    CompareOp::NEqual => RuntimeVal::Map(lmap) != RuntimeVal::Map(rmap),
+                    _ => return Err(anyhow!("only == and != supported between two map values"))
+                }
+            })
+        }
+        _ => return Err(anyhow!("only bool, num, str, list, and map supported in comparisons"))
         }
     )
 }
@@ -162,43 +524,80 @@ impl Visitor for GeneralVisitor {
     
     fn visit_program(&self, stmts: &Vec<Stmt>) -> Self::Target {
         // Visit all statements in program, evaluating each
-        for stmt in stmts {
-            _ = self.visit_stmt(stmt)?;
-        }
+        self.eval_program(stmts)?;
 
         // Return null runtime value
         Ok(RuntimeVal::Null)
     }
 
     fn visit_stmt(&self, stmt: &Stmt) -> Self::Target {
-        match stmt {
-            Stmt::Bash(_) => self.visit_bash_code_stmt(stmt),
-            Stmt::If { .. } => self.visit_stmt_if(stmt),
-            Stmt::Block(_) => self.visit_stmt_block(stmt),
-            Stmt::Print(_) => self.visit_stmt_print(stmt),
-            Stmt::Assign(_) => self.visit_stmt_assign(stmt),
-            Stmt::Expr(e) => self.visit_expr(e),
-        }
+        let result = self.step().and_then(|_| match stmt.kind {
+            StmtKind::Bash(_) => self.visit_bash_code_stmt(stmt),
+            StmtKind::If { .. } => self.visit_stmt_if(stmt),
+            StmtKind::While { .. } => self.visit_stmt_while(stmt),
+            StmtKind::For { .. } => self.visit_stmt_for(stmt),
+            StmtKind::Break => self.visit_stmt_break(stmt),
+            StmtKind::Continue => self.visit_stmt_continue(stmt),
+            StmtKind::Block(_) => self.visit_stmt_block(stmt),
+            StmtKind::Print(_) => self.visit_stmt_print(stmt),
+            StmtKind::Let(_) => self.visit_stmt_let(stmt),
+            StmtKind::Const(_) => self.visit_stmt_const(stmt),
+            StmtKind::Assign(_) => self.visit_stmt_assign(stmt),
+            StmtKind::IndexAssign(_) => self.visit_stmt_index_assign(stmt),
+            StmtKind::Fn(_) => self.visit_stmt_fn(stmt),
+            StmtKind::Return(_) => self.visit_stmt_return(stmt),
+            StmtKind::Expr(ref e) => self.visit_expr(e),
+            StmtKind::Error => Err(anyhow!("cannot run a statement with a syntax error")),
+        });
+
+        result.map_err(|e| at_span(e, stmt.span))
     }
 
     fn visit_stmt_if(&self, stmt: &Stmt) -> Self::Target {
         // The given code is run if the comparison bool value is true
-        with_extract_enum_variant!((*stmt).clone(), Stmt::If { comparison, code }, {
-            with_extract_enum_variant!(self.visit_compare_expr(&comparison)?, RuntimeVal::Bool(b), {
-                if b == true {
-                    self.visit_stmt_block(&Stmt::Block(code))?;
-                }
-            });
+        // Otherwise, the else branch is run if there is one
+        with_extract_enum_variant!(&stmt.kind, StmtKind::If { comparison, code, else_code }, {
+            if self.eval_condition(comparison)? {
+                self.with_scope(|| self.eval_program(code))?;
+            } else if let Some(else_code) = else_code {
+                self.with_scope(|| self.eval_program(else_code))?;
+            }
+        });
+
+        // Return null because this is a statement
+        Ok(RuntimeVal::Null)
+    }
+
+    fn visit_stmt_while(&self, stmt: &Stmt) -> Self::Target {
+        with_extract_enum_variant!(&stmt.kind, StmtKind::While { comparison, code }, {
+            self.in_loop(|| self.run_while_loop(comparison, code))?;
         });
 
         // Return null because this is a statement
         Ok(RuntimeVal::Null)
     }
 
+    fn visit_stmt_for(&self, stmt: &Stmt) -> Self::Target {
+        with_extract_enum_variant!(&stmt.kind, StmtKind::For { names, iterable, code }, {
+            self.in_loop(|| self.run_for_loop(names, iterable, code))?;
+        });
+
+        // Return null because this is a statement
+        Ok(RuntimeVal::Null)
+    }
+
+    fn visit_stmt_break(&self, _stmt: &Stmt) -> Self::Target {
+        self.raise_loop_signal(ControlFlow::Break, "break")
+    }
+
+    fn visit_stmt_continue(&self, _stmt: &Stmt) -> Self::Target {
+        self.raise_loop_signal(ControlFlow::Continue, "continue")
+    }
+
     fn visit_stmt_block(&self, stmt: &Stmt) -> Self::Target {
-        // Run all the code in the block
-        with_extract_enum_variant!(stmt, Stmt::Block(statements), {
-            self.visit_program(statements)?;
+        // Run all the code in the block, in its own scope
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Block(statements), {
+            self.with_scope(|| self.visit_program(statements))?;
         });
 
         // Return null because this is a statement
@@ -206,9 +605,12 @@ impl Visitor for GeneralVisitor {
     }
 
     fn visit_bash_code_stmt(&self, stmt: &Stmt) -> Self::Target {
-        with_extract_enum_variant!(stmt, Stmt::Bash(code), {
-            // Call system() func from libc
-            unsafe { libc::system(CString::new(code.as_bytes())?.as_ptr()) }
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Bash(code), {
+            self.permissions.check_exec(SHELL_PROGRAM)?;
+            let code = CString::new(code.as_bytes())?;
+
+            // Call system() func from libc, which can't be stopped early, so the timeout is only checked once it returns
+            self.with_time_left(|_| Ok(unsafe { libc::system(code.as_ptr()) }))?;
         });
         
         // Return null because this is a statement
@@ -216,100 +618,379 @@ impl Visitor for GeneralVisitor {
     }
 
     fn visit_stmt_print(&self, stmt: &Stmt) -> Self::Target {
-        with_extract_enum_variant!(stmt, Stmt::Print(print_stmt), {
-            // Get runtime value
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Print(print_stmt), {
+            // Get runtime value, failing if it is an undeclared var
             let runtime_assign_value = self.visit_expr(&print_stmt.value)?;
             
             // Print runtime value
-            print_runtime_val(&self.env, &runtime_assign_value);
+            self.console.write_line(&runtime_assign_value.to_string())?;
+        });
 
-            // Return null because it doesn't eval to anything
-            return Ok(RuntimeVal::Null);
+        // Return null because it doesn't eval to anything
+        Ok(RuntimeVal::Null)
+    }
+
+    fn visit_stmt_let(&self, stmt: &Stmt) -> Self::Target {
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Let(a), {
+            // Declare var in current scope
+            let value = self.visit_expr(&a.value)?;
+            self.env().declare_var(&a.name, value)?;
         });
+
+        // Return null because this doesn't eval to anything
+        Ok(RuntimeVal::Null)
+    }
+
+    fn visit_stmt_const(&self, stmt: &Stmt) -> Self::Target {
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Const(a), {
+            // Declare constant in current scope
+            let value = self.visit_expr(&a.value)?;
+            self.env().declare_const(&a.name, value)?;
+        });
+
+        // Return null because this doesn't eval to anything
+        Ok(RuntimeVal::Null)
     }
 
     fn visit_stmt_assign(&self, stmt: &Stmt) -> Self::Target {
-        with_extract_enum_variant!(stmt, Stmt::Assign(a), {
-            // Insert var into runtime environment
-            self.env.symbols.borrow_mut().insert(a.name.clone(), self.visit_expr(&a.value)?);
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Assign(a), {
+            // Assign var in the nearest scope defining it
+            let value = self.visit_expr(&a.value)?;
+            self.env().assign_var(&a.name, value)?;
         });
 
         // Return null because this doesn't eval to anything
-        return Ok(RuntimeVal::Null);
-    }
-
-    fn visit_expr(&self, expr: &Box<Expr>) -> Self::Target {
-        match **expr {
-            Expr::Read => self.visit_read_expr(expr),
-            Expr::Binary(_) => self.visit_binary_expr(expr),
-            Expr::Comparison { .. } => self.visit_compare_expr(expr),
-            Expr::Bool(_) => self.visit_bool_expr(expr),
-            Expr::Str(_) => self.visit_str_expr(expr),
-            Expr::Num(_) => self.visit_num_expr(expr),
-            Expr::Ident(_) => self.visit_ident_expr(expr)
-        }
+        Ok(RuntimeVal::Null)
     }
 
-    fn visit_compare_expr(&self, expr: &Box<Expr>) -> Self::Target {
-        Ok(with_extract_enum_variant!((**expr).clone(), Expr::Comparison {lhs, op, rhs}, {
-            let eval_lhs = self.visit_expr(&lhs)?;
-            let eval_rhs = self.visit_expr(&rhs)?;
+    fn visit_stmt_index_assign(&self, stmt: &Stmt) -> Self::Target {
+        with_extract_enum_variant!(&stmt.kind, StmtKind::IndexAssign(a), {
+            // Changes are seen by every value sharing the list or map
+            match self.visit_expr(&a.target)? {
+                RuntimeVal::List(items) => {
+                    let len = items.borrow().len();
+                    let position = self.eval_list_position(&a.index, len, false)?;
+                    let value = self.visit_expr(&a.value)?;
+
+                    // Replace item, checking the position again in case evaluating the value changed the list
+                    let mut items = items.borrow_mut();
+                    let len = items.len();
+                    let item = items.get_mut(position)
+                        .ok_or_else(|| anyhow!("list index {position} is out of range for list of length {len}"))?;
+                    *item = value;
+                }
+                RuntimeVal::Map(map) => {
+                    let key = self.eval_map_key(&a.index)?;
+                    let value = self.visit_expr(&a.value)?;
+
+                    // Only inserting a new key makes the map bigger
+                    if map.borrow().get(&key).is_none() {
+                        self.check_collection_len(map.borrow().len() + 1)?;
+                    }
+
+                    // Insert or replace key
+                    map.borrow_mut().insert(key, value);
+                }
+                val => return Err(anyhow!("only lists and maps can be indexed, but tried to index {val}"))
+            }
+        });
 
-            RuntimeVal::Bool(get_equality(&self.env, eval_lhs, op, eval_rhs)?)
+        // Return null because this doesn't eval to anything
+        Ok(RuntimeVal::Null)
+    }
+
+    fn visit_stmt_fn(&self, stmt: &Stmt) -> Self::Target {
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Fn(f), {
+            // Declare function in current scope, holding the scope unless it is global
+            let env = self.env();
+            let function = FunctionVal {
+                decl: f.clone(),
+                env: env.parent.is_some().then(|| Rc::downgrade(&env))
+            };
+            env.declare_var(&f.name, RuntimeVal::Function(function))?;
+        });
+
+        // Return null because this doesn't eval to anything
+        Ok(RuntimeVal::Null)
+    }
+
+    fn visit_stmt_return(&self, stmt: &Stmt) -> Self::Target {
+        with_extract_enum_variant!(&stmt.kind, StmtKind::Return(value), {
+            if self.call_depth.get() == 0 {
+                return Err(anyhow!("'return' can only be used inside of a function"));
+            }
+
+            // Evaluate returned value before the call frame is dropped
+            let value = match value {
+                Some(value) => self.visit_expr(value)?,
+                None => RuntimeVal::Null
+            };
+
+            // Set signal so enclosing statements stop evaluating
+            *self.control_flow.borrow_mut() = Some(ControlFlow::Return(value));
+        });
+
+        // Return null because this is a statement
+        Ok(RuntimeVal::Null)
+    }
+
+    fn visit_expr(&self, expr: &Expr) -> Self::Target {
+        let result = self.step().and_then(|_| match expr.kind {
+            ExprKind::Read => self.visit_read_expr(expr),
+            ExprKind::Binary(_) => self.visit_binary_expr(expr),
+            ExprKind::Neg(_) => self.visit_neg_expr(expr),
+            ExprKind::Comparison { .. } => self.visit_compare_expr(expr),
+            ExprKind::Logical { .. } => self.visit_logical_expr(expr),
+            ExprKind::Not(_) => self.visit_not_expr(expr),
+            ExprKind::Bool(_) => self.visit_bool_expr(expr),
+            ExprKind::Str(_) => self.visit_str_expr(expr),
+            ExprKind::Interp(_) => self.visit_interp_expr(expr),
+            ExprKind::Shell(_) => self.visit_shell_expr(expr),
+            ExprKind::Num(_) => self.visit_num_expr(expr),
+            ExprKind::Ident(_) => self.visit_ident_expr(expr),
+            ExprKind::Call(_) => self.visit_call_expr(expr),
+            ExprKind::Range { .. } => self.visit_range_expr(expr),
+            ExprKind::List(_) => self.visit_list_expr(expr),
+            ExprKind::Map(_) => self.visit_map_expr(expr),
+            ExprKind::Index { .. } => self.visit_index_expr(expr),
+            ExprKind::Slice { .. } => self.visit_slice_expr(expr)
+        });
+
+        result.map_err(|e| at_span(e, expr.span))
+    }
+
+    fn visit_compare_expr(&self, expr: &Expr) -> Self::Target {
+        Ok(with_extract_enum_variant!(&expr.kind, ExprKind::Comparison {lhs, op, rhs}, {
+            let eval_lhs = self.visit_expr(lhs)?;
+            let eval_rhs = self.visit_expr(rhs)?;
+
+            RuntimeVal::Bool(get_equality(eval_lhs, op.clone(), eval_rhs)?)
         }))
     }
 
-    fn visit_read_expr(&self, _expr: &Box<Expr>) -> Self::Target {
-        // Get terminal input
-        let mut terminal_input = String::new();
-        _ = std::io::stdin().lock().read_line(&mut terminal_input)?;
+    fn visit_logical_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Logical { lhs, op, rhs }, {
+            let lbool = self.eval_condition(lhs)?;
+
+            // Only evaluate rhs if lhs doesn't already decide the result
+            Ok(RuntimeVal::Bool(match op {
+                LogicalOp::And => lbool && self.eval_condition(rhs)?,
+                LogicalOp::Or => lbool || self.eval_condition(rhs)?
+            }))
+        })
+    }
+
+    fn visit_not_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Not(value), {
+            Ok(RuntimeVal::Bool(!self.eval_condition(value)?))
+        })
+    }
+
+    fn visit_read_expr(&self, _expr: &Expr) -> Self::Target {
+        self.permissions.check(Capability::Stdin)?;
 
-        // Return terminal input as runtime string
-        Ok(RuntimeVal::Str(terminal_input))
+        // Return a line of console input as runtime string, which can't be stopped early, so the timeout is only checked once it returns
+        Ok(RuntimeVal::Str(self.with_time_left(|_| self.console.read_line())?))
     }
 
-    fn visit_bool_expr(&self, expr: &Box<Expr>) -> Self::Target {
-        with_extract_enum_variant!(**expr, Expr::Bool(b), {
+    fn visit_bool_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(expr.kind, ExprKind::Bool(b), {
             Ok(RuntimeVal::Bool(b))
         })
     }
 
-    fn visit_str_expr(&self, expr: &Box<Expr>) -> Self::Target {
-        with_extract_enum_variant!((**expr).clone(), Expr::Str(s), {
-            return Ok(RuntimeVal::Str(s));
-        });
+    fn visit_str_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(expr.kind.clone(), ExprKind::Str(s), {
+            Ok(RuntimeVal::Str(s))
+        })
+    }
+
+    fn visit_interp_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Interp(parts), {
+            // Insert values formatted the same way `print` formats them
+            let mut s = String::new();
+            for part in parts {
+                match part {
+                    InterpPart::Str(text) => s.push_str(text),
+                    InterpPart::Expr(value) => s.push_str(&self.visit_expr(value)?.to_string())
+                }
+            }
+
+            Ok(RuntimeVal::Str(s))
+        })
     }
 
-    fn visit_binary_expr(&self, expr: &Box<Expr>) -> Self::Target {
-        with_extract_enum_variant!((**expr).clone(), Expr::Binary(b), {
+    fn visit_shell_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Shell(parts), {
+            self.permissions.check_exec(SHELL_PROGRAM)?;
+
+            // Quote inserted values so the shell reads them as plain words, with lists becoming one word per item
+            let mut command = String::new();
+            for part in parts {
+                match part {
+                    InterpPart::Str(text) => command.push_str(text),
+                    InterpPart::Expr(value) => match self.visit_expr(value)? {
+                        RuntimeVal::List(items) => {
+                            let words = items.borrow().iter()
+                                .map(|item| arg_to_string(item.clone()).map(|arg| quote(&arg)))
+                                .collect::<anyhow::Result<Vec<_>>>()?;
+                            command.push_str(&words.join(" "));
+                        }
+                        value => command.push_str(&quote(&value.to_string()))
+                    }
+                }
+            }
+
+            let output = self.with_time_left(|timeout| run_shell(&command, &RunOptions { timeout, ..RunOptions::default() }))?;
+            Ok(output.to_runtime_val())
+        })
+    }
+
+    fn visit_binary_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Binary(b), {
             // Evaluate left and right side of binary expr
             let runtime_lhs_val = self.visit_expr(&b.lhs)?;
             let runtime_rhs_val = self.visit_expr(&b.rhs)?;
 
             // Return value of binary expression
-            with_extract_enum_variant!{runtime_lhs_val, RuntimeVal::Num(l), {
-                with_extract_enum_variant!{runtime_rhs_val, RuntimeVal::Num(r), {
-                    return Ok(RuntimeVal::Num(match b.op {
-                        Op::Plus => l + r,
-                        Op::Minus => l - r,
-                        Op::Mult => l * r,
-                        Op::Div => l / r
-                    }));
-                }
-            }}}
-        });
+            match (runtime_lhs_val, runtime_rhs_val) {
+                (RuntimeVal::Num(l), RuntimeVal::Num(r)) => Ok(RuntimeVal::Num(match b.op {
+                    Op::Plus => l + r,
+                    Op::Minus => l - r,
+                    Op::Mult => l * r,
+                    Op::Div => l / r
+                })),
+                (l, r) => Err(anyhow!("arithmetic is only supported between two numbers, but got {l} and {r}"))
+            }
+        })
     }
 
-    fn visit_num_expr(&self, expr: &Box<Expr>) -> Self::Target {
-        with_extract_enum_variant!(**expr, Expr::Num(n), {
-            return Ok(RuntimeVal::Num(n));
-        });
+    fn visit_neg_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Neg(value), {
+            match self.visit_expr(value)? {
+                RuntimeVal::Num(n) => Ok(RuntimeVal::Num(-n)),
+                val => Err(anyhow!("only numbers can be negated, but got {val}"))
+            }
+        })
     }
 
-    fn visit_ident_expr(&self, expr: &Box<Expr>) -> Self::Target {
-        with_extract_enum_variant!((**expr).clone(), Expr::Ident(i), {
-            // Return runtime type representing ident
-            return Ok(RuntimeVal::Ident(i.to_owned()));
-        });
+    fn visit_num_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(expr.kind, ExprKind::Num(n), {
+            Ok(RuntimeVal::Num(n))
+        })
+    }
+
+    fn visit_ident_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Ident(name), {
+            // Look up the value of the var now, so every operator gets a real value
+            self.env().get_var(name).ok_or_else(|| anyhow!("undefined variable `{name}`"))
+        })
+    }
+
+    fn visit_call_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Call(call), {
+            // Get function being called, which is builtin if no function with its name is declared
+            let function = match self.env().get_var(&call.name) {
+                Some(f @ (RuntimeVal::Function(_) | RuntimeVal::Native(_))) => Some(f),
+                Some(_) => return Err(anyhow!("`{}` is not a function", call.name)),
+                None if is_builtin(&call.name) => None,
+                None => return Err(anyhow!("undefined function `{}`", call.name))
+            };
+
+            // Evaluate args in the caller's scope
+            let mut args = Vec::new();
+            for arg in &call.args {
+                args.push(self.visit_expr(arg)?);
+            }
+
+            match function {
+                Some(RuntimeVal::Function(function)) => self.call_function(&function, args),
+                Some(RuntimeVal::Native(native)) => native.call(args),
+                _ => self.with_time_left(|time_left| call_builtin(&call.name, args, &self.permissions, time_left))
+            }
+        })
+    }
+
+    fn visit_range_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Range { start, end }, {
+            let start = self.eval_range_bound(start)?;
+            let end = self.eval_range_bound(end)?;
+            Ok(RuntimeVal::Range(start, end))
+        })
+    }
+
+    fn visit_list_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::List(items), {
+            self.check_collection_len(items.len())?;
+
+            // Evaluate every item
+            let mut values = Vec::new();
+            for item in items {
+                values.push(self.visit_expr(item)?);
+            }
+
+            Ok(RuntimeVal::List(Rc::new(RefCell::new(values))))
+        })
+    }
+
+    fn visit_map_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Map(entries), {
+            // Evaluate every key and value, in order
+            let mut map = RuntimeMap::default();
+            for (key, value) in entries {
+                let key = self.eval_map_key(key)?;
+                map.insert(key, self.visit_expr(value)?);
+            }
+            self.check_collection_len(map.len())?;
+
+            Ok(RuntimeVal::Map(Rc::new(RefCell::new(map))))
+        })
+    }
+
+    fn visit_index_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Index { target, index }, {
+            match self.visit_expr(target)? {
+                RuntimeVal::List(items) => {
+                    let len = items.borrow().len();
+                    let position = self.eval_list_position(index, len, false)?;
+
+                    // Return indexed item
+                    let item = items.borrow()[position].clone();
+                    Ok(item)
+                }
+                RuntimeVal::Map(map) => {
+                    let key = self.eval_map_key(index)?;
+
+                    // Return value of key
+                    let value = map.borrow().get(&key).cloned();
+                    value.ok_or_else(|| anyhow!("map has no key '{key}'"))
+                }
+                val => Err(anyhow!("only lists and maps can be indexed, but tried to index {val}"))
+            }
+        })
+    }
+
+    fn visit_slice_expr(&self, expr: &Expr) -> Self::Target {
+        with_extract_enum_variant!(&expr.kind, ExprKind::Slice { target, start, end }, {
+            let items = self.eval_list(target)?;
+            let len = items.borrow().len();
+
+            // Missing bounds default to the start and end of the list
+            let start = match start {
+                Some(start) => self.eval_list_position(start, len, true)?,
+                None => 0
+            };
+            let end = match end {
+                Some(end) => self.eval_list_position(end, len, true)?,
+                None => len
+            };
+            if start > end {
+                return Err(anyhow!("slice start {start} is past slice end {end}"));
+            }
+
+            // Return a new list holding the sliced items
+            let sliced = items.borrow()[start..end].to_vec();
+            Ok(RuntimeVal::List(Rc::new(RefCell::new(sliced))))
+        })
     }
 }
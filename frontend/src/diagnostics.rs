@@ -0,0 +1,192 @@
+/*!
+    Contains spans of source code, and diagnostics that describe errors found at them.
+*/
+
+use std::fmt;
+use lalrpop_util::ParseError;
+use crate::lexer::{LexError, Tok};
+
+/// A span of source code, as start and end byte positions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+
+impl Span {
+    /// Constructs a new span from `start` up to but not including `end`.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the line and column (both starting at 1) that the span starts at in `source`.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let start = floor_char_boundary(source, self.start);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+
+        (source[..start].matches('\n').count() + 1, source[line_start..start].chars().count() + 1)
+    }
+}
+
+/// Returns the largest char boundary of `source` at or before `pos`.
+fn floor_char_boundary(source: &str, pos: usize) -> usize {
+    let mut pos = pos.min(source.len());
+    while !source.is_char_boundary(pos) {
+        pos -= 1;
+    }
+
+    pos
+}
+
+/// An error found in source code, along with the span of code it was found at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// A description of the error.
+    pub message: String,
+    /// The span of source code the error was found at.
+    pub span: Span,
+    /// An explanation of how the error could be fixed, if any.
+    pub help: Option<String>
+}
+
+impl Diagnostic {
+    /// Constructs a new diagnostic for an error found at `span`.
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self { message: message.into(), span, help: None }
+    }
+
+    /// Returns the diagnostic with an explanation of how the error could be fixed.
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    /// Renders the diagnostic along with the line of `source` it was found at, underlining the span with carets.
+    ///
+    /// `name` is the name of the file `source` was read from.
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let (line, col) = self.span.line_col(source);
+
+        // Find the line holding the start of the span
+        let start = floor_char_boundary(source, self.span.start);
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Underline up to the end of the line, keeping tabs so the carets line up
+        let end = floor_char_boundary(source, self.span.end.clamp(start, line_end));
+        let indent: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(source[start..end].chars().count().max(1));
+
+        let gutter = " ".repeat(line.to_string().len());
+        let mut rendered = format!(
+            "error: {}\n{gutter}--> {name}:{line}:{col}\n{gutter} |\n{line} | {line_text}\n{gutter} | {indent}{carets}",
+            self.message
+        );
+        if let Some(help) = &self.help {
+            rendered.push_str(&format!("\n{gutter} = help: {help}"));
+        }
+
+        rendered
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Every diagnostic found in the same source code.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Diagnostics {
+    /// Renders every diagnostic along with the line of `source` it was found at, separated by blank lines.
+    ///
+    /// `name` is the name of the file `source` was read from.
+    pub fn render(&self, name: &str, source: &str) -> String {
+        self.0.iter()
+            .map(|d| d.render(name, source))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<_> = self.0.iter().map(|d| d.message.as_str()).collect();
+        write!(f, "{}", messages.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+impl From<LexError> for Diagnostic {
+    fn from(error: LexError) -> Self {
+        Diagnostic::new(error.message, Span::new(error.start, error.end))
+    }
+}
+
+impl From<ParseError<usize, Tok<'_>, LexError>> for Diagnostic {
+    fn from(error: ParseError<usize, Tok<'_>, LexError>) -> Self {
+        match error {
+            ParseError::InvalidToken { location } => Diagnostic::new("invalid token", Span::new(location, location + 1)),
+            ParseError::UnrecognizedEof { location, expected } => {
+                Diagnostic::new("unexpected end of file", Span::new(location, location))
+                    .with_help(describe_expected(&expected))
+            }
+            ParseError::UnrecognizedToken { token: (start, tok, end), expected } => {
+                Diagnostic::new(format!("unexpected '{tok}'"), Span::new(start, end))
+                    .with_help(describe_expected(&expected))
+            }
+            ParseError::ExtraToken { token: (start, tok, end) } => {
+                Diagnostic::new(format!("unexpected extra '{tok}'"), Span::new(start, end))
+            }
+            ParseError::User { error } => error.into()
+        }
+    }
+}
+
+/// Describes the tokens the parser expected, which are already quoted.
+fn describe_expected(expected: &[String]) -> String {
+    match expected {
+        [tok] => format!("expected {tok}"),
+        _ => format!("expected one of {}", expected.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+
+    #[test]
+    fn test_span_line_col() {
+        let source = "let x = 1;\n  print é;";
+
+        assert_eq!(Span::new(4, 5).line_col(source), (1, 5));
+        assert_eq!(Span::new(19, 21).line_col(source), (2, 9));
+    }
+
+    #[test]
+    fn test_diagnostic_render() {
+        let source = "let x = 1;\nprint x + \"a\";\n";
+        let diagnostic = Diagnostic::new("cannot add num and str", Span::new(17, 24))
+            .with_help("convert both values to the same type");
+
+        assert_eq!(diagnostic.render("main.seel", source), [
+            "error: cannot add num and str",
+            " --> main.seel:2:7",
+            "  |",
+            "2 | print x + \"a\";",
+            "  |       ^^^^^^^",
+            "  = help: convert both values to the same type"
+        ].join("\n"));
+    }
+}
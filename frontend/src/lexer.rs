@@ -0,0 +1,495 @@
+/*!
+    Contains the lexer that splits source code into tokens for the parser.
+*/
+
+use std::fmt;
+
+/// A token along with its start and end byte positions in the source code, or the error found lexing it.
+pub type Spanned<'input> = Result<(usize, Tok<'input>, usize), LexError>;
+
+/// A token of source code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tok<'input> {
+    // Keywords
+    If,
+    Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
+    Fn,
+    Return,
+    Let,
+    Const,
+    Print,
+    Read,
+    True,
+    False,
+    And,
+    Or,
+    Not,
+
+    // Punctuation
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Semicolon,
+    Question,
+    Colon,
+    Dot,
+    DotDot,
+    Assign,
+    Equal,
+    NEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    SingleQuote,
+
+    /// A name of a variable or function.
+    Name(&'input str),
+    /// A numeric literal.
+    Num(f64),
+    /// A string literal in double quotes, including its quotes.
+    QuotedStr(&'input str),
+    /// A string literal in triple double quotes, including its quotes.
+    TripleQuotedStr(&'input str),
+    /// A raw string literal, including its `r` and quotes.
+    RawStr(&'input str),
+    /// Shell code in backticks, including its backticks.
+    ShellStr(&'input str)
+}
+
+/// The keywords of the language, along with their tokens.
+const KEYWORDS: &[(&str, Tok<'static>)] = &[
+    ("if", Tok::If),
+    ("else", Tok::Else),
+    ("while", Tok::While),
+    ("for", Tok::For),
+    ("in", Tok::In),
+    ("break", Tok::Break),
+    ("continue", Tok::Continue),
+    ("fn", Tok::Fn),
+    ("return", Tok::Return),
+    ("let", Tok::Let),
+    ("const", Tok::Const),
+    ("print", Tok::Print),
+    ("read", Tok::Read),
+    ("true", Tok::True),
+    ("false", Tok::False),
+    ("and", Tok::And),
+    ("or", Tok::Or),
+    ("not", Tok::Not)
+];
+
+impl fmt::Display for Tok<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Keywords are written the same as in source code
+        if let Some((keyword, _)) = KEYWORDS.iter().find(|(_, tok)| tok == self) {
+            return write!(f, "{keyword}");
+        }
+
+        match self {
+            Tok::LBrace => write!(f, "{{"),
+            Tok::RBrace => write!(f, "}}"),
+            Tok::LParen => write!(f, "("),
+            Tok::RParen => write!(f, ")"),
+            Tok::LBracket => write!(f, "["),
+            Tok::RBracket => write!(f, "]"),
+            Tok::Comma => write!(f, ","),
+            Tok::Semicolon => write!(f, ";"),
+            Tok::Question => write!(f, "?"),
+            Tok::Colon => write!(f, ":"),
+            Tok::Dot => write!(f, "."),
+            Tok::DotDot => write!(f, ".."),
+            Tok::Assign => write!(f, "="),
+            Tok::Equal => write!(f, "=="),
+            Tok::NEqual => write!(f, "!="),
+            Tok::Less => write!(f, "<"),
+            Tok::Greater => write!(f, ">"),
+            Tok::LessEqual => write!(f, "<="),
+            Tok::GreaterEqual => write!(f, ">="),
+            Tok::Plus => write!(f, "+"),
+            Tok::Minus => write!(f, "-"),
+            Tok::Star => write!(f, "*"),
+            Tok::Slash => write!(f, "/"),
+            Tok::SingleQuote => write!(f, "'"),
+            Tok::Num(n) => write!(f, "{n}"),
+            Tok::Name(s) | Tok::QuotedStr(s) | Tok::TripleQuotedStr(s) | Tok::RawStr(s) | Tok::ShellStr(s) => write!(f, "{s}"),
+            _ => unreachable!("keywords are written above")
+        }
+    }
+}
+
+/// An error found in source code while lexing it, or while processing a literal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    /// A description of the error.
+    pub message: String,
+    /// The start byte position of the error in the source code.
+    pub start: usize,
+    /// The end byte position of the error in the source code.
+    pub end: usize
+}
+
+impl LexError {
+    /// Constructs a new lex error spanning from `start` to `end`.
+    pub fn new(message: impl Into<String>, start: usize, end: usize) -> Self {
+        Self { message: message.into(), start, end }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// A lexer that splits source code into tokens, skipping whitespace and comments.
+///
+/// Line comments start with `//`, and block comments are wrapped in `/*` and `*/` and can be nested.
+#[derive(Clone, Debug)]
+pub struct Lexer<'input> {
+    /// The source code being lexed.
+    source: &'input str,
+    /// The byte position of the next character to lex.
+    pos: usize,
+    /// The byte position of `source` in the source code it was taken from, added to every span.
+    offset: usize
+}
+
+impl<'input> Lexer<'input> {
+    /// Constructs a new lexer over `source`.
+    pub fn new(source: &'input str) -> Self {
+        Self::with_offset(source, 0)
+    }
+
+    /// Constructs a new lexer over `source`, which starts at byte position `offset` of the source code it was taken from.
+    pub fn with_offset(source: &'input str, offset: usize) -> Self {
+        Self { source, pos: 0, offset }
+    }
+
+    /// Returns the character `n` characters after the next one, without consuming anything.
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.source[self.pos..].chars().nth(n)
+    }
+
+    /// Returns the next character, without consuming it.
+    fn peek(&self) -> Option<char> {
+        self.peek_nth(0)
+    }
+
+    /// Consumes and returns the next character.
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    /// Consumes the next character if it is `c`, returning true if it was consumed.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skips whitespace and comments until the start of the next token.
+    fn skip_whitespace_and_comments(&mut self) -> Result<(), LexError> {
+        loop {
+            match (self.peek(), self.peek_nth(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('/'), Some('/')) => {
+                    // Skip until end of line
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                (Some('/'), Some('*')) => self.skip_block_comment()?,
+                _ => return Ok(())
+            }
+        }
+    }
+
+    /// Skips a block comment, including any block comments nested inside of it.
+    fn skip_block_comment(&mut self) -> Result<(), LexError> {
+        let start = self.pos;
+        let mut depth = 0;
+
+        loop {
+            match (self.bump(), self.peek()) {
+                (Some('/'), Some('*')) => {
+                    self.bump();
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    self.bump();
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                (Some(_), _) => {}
+                (None, _) => return Err(LexError::new("unterminated block comment", start, start + 2))
+            }
+        }
+    }
+
+    /// Lexes a name, keyword or raw string, whose first character was already consumed.
+    fn lex_word(&mut self, start: usize) -> Spanned<'input> {
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+            self.bump();
+        }
+        let word = &self.source[start..self.pos];
+
+        // A lone `r` followed by a quote starts a raw string
+        if word == "r" && self.eat('"') {
+            while self.peek().is_some_and(|c| c != '"') {
+                self.bump();
+            }
+            if !self.eat('"') {
+                return Err(LexError::new("unterminated raw string", start, self.pos));
+            }
+
+            return Ok((start, Tok::RawStr(&self.source[start..self.pos]), self.pos));
+        }
+
+        let tok = match KEYWORDS.iter().find(|(keyword, _)| *keyword == word) {
+            Some((_, tok)) => tok.clone(),
+            None => Tok::Name(word)
+        };
+        Ok((start, tok, self.pos))
+    }
+
+    /// Lexes a numeric literal, whose first digit was already consumed.
+    fn lex_num(&mut self, start: usize) -> Spanned<'input> {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+        }
+
+        // Only treat a dot as a decimal point if a digit follows, so ranges like `0..10` still lex
+        if self.peek() == Some('.') && self.peek_nth(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.bump();
+            }
+        }
+
+        let text = &self.source[start..self.pos];
+        match text.parse() {
+            Ok(n) => Ok((start, Tok::Num(n), self.pos)),
+            Err(_) => Err(LexError::new(format!("invalid number '{text}'"), start, self.pos))
+        }
+    }
+
+    /// Lexes a string literal, whose opening quote was already consumed.
+    fn lex_str(&mut self, start: usize) -> Spanned<'input> {
+        // Three quotes start a triple-quoted string
+        if self.source[self.pos..].starts_with("\"\"") {
+            self.pos += 2;
+            match self.source[self.pos..].find("\"\"\"") {
+                Some(end) => self.pos += end + 3,
+                None => {
+                    self.pos = self.source.len();
+                    return Err(LexError::new("unterminated triple-quoted string", start, start + 3));
+                }
+            }
+
+            return Ok((start, Tok::TripleQuotedStr(&self.source[start..self.pos]), self.pos));
+        }
+
+        self.skip_quoted_str(start)?;
+        Ok((start, Tok::QuotedStr(&self.source[start..self.pos]), self.pos))
+    }
+
+    /// Skips the rest of a string literal in double quotes, whose opening quote was already consumed.
+    ///
+    /// Strings inside of `{expr}` placeholders are skipped too, so they can hold quotes.
+    fn skip_quoted_str(&mut self, start: usize) -> Result<(), LexError> {
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(LexError::new(
+                    "unterminated string, use a triple-quoted string for strings spanning multiple lines",
+                    start,
+                    start + 1
+                )),
+                Some('\\') => {
+                    // Escape sequences are checked once the string is parsed
+                    self.bump();
+                }
+                Some('"') => return Ok(()),
+                Some('{') if self.eat('{') => {}
+                Some('{') => self.skip_placeholder(),
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Skips the rest of a `{expr}` placeholder, whose opening brace was already consumed.
+    ///
+    /// If the placeholder isn't closed on the same line, nothing is skipped, so the brace is left for the string parser to report.
+    fn skip_placeholder(&mut self) {
+        let start = self.pos;
+        let mut depth = 1;
+
+        while depth > 0 {
+            let closed = match self.bump() {
+                None | Some('\n') => false,
+                Some('"') => self.skip_quoted_str(self.pos - 1).is_ok(),
+                Some('{') => { depth += 1; true }
+                Some('}') => { depth -= 1; true }
+                Some(_) => true
+            };
+
+            if !closed {
+                self.pos = start;
+                return;
+            }
+        }
+    }
+
+    /// Lexes shell code in backticks, whose opening backtick was already consumed.
+    ///
+    /// Shell code can span multiple lines, and strings inside of `{expr}` placeholders are skipped so they can hold backticks.
+    fn lex_shell_str(&mut self, start: usize) -> Spanned<'input> {
+        loop {
+            match self.bump() {
+                None => return Err(LexError::new("unterminated shell code, expected a closing '`'", start, start + 1)),
+                Some('`') => break,
+                Some('{') if self.eat('{') => {}
+                Some('{') => self.skip_placeholder(),
+                Some(_) => {}
+            }
+        }
+
+        Ok((start, Tok::ShellStr(&self.source[start..self.pos]), self.pos))
+    }
+
+    /// Returns the next token, with its span relative to the start of `source`.
+    fn next_unshifted(&mut self) -> Option<Spanned<'input>> {
+        if let Err(e) = self.skip_whitespace_and_comments() {
+            return Some(Err(e));
+        }
+
+        let start = self.pos;
+        let tok = match self.bump()? {
+            '{' => Tok::LBrace,
+            '}' => Tok::RBrace,
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            '[' => Tok::LBracket,
+            ']' => Tok::RBracket,
+            ',' => Tok::Comma,
+            ';' => Tok::Semicolon,
+            '?' => Tok::Question,
+            ':' => Tok::Colon,
+            '+' => Tok::Plus,
+            '-' => Tok::Minus,
+            '*' => Tok::Star,
+            '/' => Tok::Slash,
+            '\'' => Tok::SingleQuote,
+            '.' => if self.eat('.') { Tok::DotDot } else { Tok::Dot },
+            '=' => if self.eat('=') { Tok::Equal } else { Tok::Assign },
+            '<' => if self.eat('=') { Tok::LessEqual } else { Tok::Less },
+            '>' => if self.eat('=') { Tok::GreaterEqual } else { Tok::Greater },
+            '!' if self.eat('=') => Tok::NEqual,
+            '"' => return Some(self.lex_str(start)),
+            '`' => return Some(self.lex_shell_str(start)),
+            c if c.is_ascii_digit() => return Some(self.lex_num(start)),
+            c if c.is_ascii_alphabetic() || c == '_' => return Some(self.lex_word(start)),
+            c => return Some(Err(LexError::new(format!("unexpected character '{c}'"), start, self.pos)))
+        };
+
+        Some(Ok((start, tok, self.pos)))
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned<'input>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        self.next_unshifted().map(|t| match t {
+            Ok((start, tok, end)) => Ok((start + offset, tok, end + offset)),
+            Err(e) => Err(LexError::new(e.message, e.start + offset, e.end + offset))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+
+    /// Returns the tokens of `source`, panicking on errors.
+    fn lex(source: &str) -> Vec<Tok<'_>> {
+        Lexer::new(source).map(|t| t.unwrap().1).collect()
+    }
+
+    #[test]
+    fn test_lexer_spans() {
+        let tokens: Vec<_> = Lexer::new("let x2 = 0..10;").map(|t| t.unwrap()).collect();
+
+        assert_eq!(tokens, vec![
+            (0, Tok::Let, 3),
+            (4, Tok::Name("x2"), 6),
+            (7, Tok::Assign, 8),
+            (9, Tok::Num(0.0), 10),
+            (10, Tok::DotDot, 12),
+            (12, Tok::Num(10.0), 14),
+            (14, Tok::Semicolon, 15)
+        ]);
+    }
+
+    #[test]
+    fn test_lexer_comments() {
+        let source = "
+            // A line comment
+            print 1; // A trailing comment
+            /* A block /* with a nested block */ comment */ print 2.5;
+        ";
+
+        assert_eq!(lex(source), vec![
+            Tok::Print, Tok::Num(1.0), Tok::Semicolon,
+            Tok::Print, Tok::Num(2.5), Tok::Semicolon
+        ]);
+        assert!(Lexer::new("/* /* */").any(|t| t.is_err()));
+    }
+
+    #[test]
+    fn test_lexer_strs() {
+        assert_eq!(lex(r#""a\"b" r"c\d""#), vec![Tok::QuotedStr(r#""a\"b""#), Tok::RawStr(r#"r"c\d""#)]);
+        assert_eq!(lex("\"\"\"a\n\"b\" \"\"\""), vec![Tok::TripleQuotedStr("\"\"\"a\n\"b\" \"\"\"")]);
+
+        // Placeholders can hold strings
+        assert_eq!(lex(r#""x{m["k"]}y""#), vec![Tok::QuotedStr(r#""x{m["k"]}y""#)]);
+
+        // Shell code can span lines, and its placeholders can hold strings with backticks
+        assert_eq!(lex("`ls\n{\"`\"}`"), vec![Tok::ShellStr("`ls\n{\"`\"}`")]);
+
+        // A brace that doesn't open a placeholder doesn't stop the closing quote from ending the string
+        assert_eq!(lex(r#"print "{";"#), vec![Tok::Print, Tok::QuotedStr(r#""{""#), Tok::Semicolon]);
+        assert_eq!(lex(r#"'"echo {"';"#), vec![Tok::SingleQuote, Tok::QuotedStr(r#""echo {""#), Tok::SingleQuote, Tok::Semicolon]);
+        assert_eq!(lex("`echo {`"), vec![Tok::ShellStr("`echo {`")]);
+
+        assert!(Lexer::new("\"a\nb\"").any(|t| t.is_err()));
+        assert!(Lexer::new("`ls").any(|t| t.is_err()));
+        assert!(Lexer::new("#").any(|t| t.is_err()));
+    }
+}
@@ -1,6 +1,9 @@
-// Add lalrpop parsed file
+// Add lalrpop parsed file, which is generated so isn't linted
+#[allow(clippy::all)]
 pub mod grammar;
 
 // Mod declarations
-pub mod helper;
+pub mod diagnostics;
+pub mod lexer;
 pub mod parser;
+pub mod strings;
@@ -1,20 +1,74 @@
-/// A statement.
+use crate::{diagnostics::{Diagnostic, Diagnostics, Span}, grammar, lexer::Lexer};
+
+/// How deep statements and expressions can be nested in a program, so walking its ast can't overflow the stack.
+pub const MAX_NESTING_DEPTH: usize = 256;
+
+/// A statement, along with the span of source code it was parsed from.
+#[derive(Clone, Debug)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span
+}
+
+impl Stmt {
+    /// Constructs a new statement of `kind` spanning `span`.
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+// Spans are ignored, so that the same code parsed from different places is equal
+impl PartialEq for Stmt {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+/// A kind of statement.
 #[derive(Clone, Debug, PartialEq)]
-pub enum Stmt {
-    /// An if statement.
-    If { comparison: Box<Expr>, code: Vec<Stmt> },
+pub enum StmtKind {
+    /// An if statement, with an optional else branch.
+    /// 
+    /// `comparison` can be any expression that evaluates to a bool.
+    /// An `else if` chain is stored as an else branch holding a single if statement.
+    If { comparison: Box<Expr>, code: Vec<Stmt>, else_code: Option<Vec<Stmt>> },
+
+    /// A while loop, running for as long as `comparison` evaluates to true.
+    While { comparison: Box<Expr>, code: Vec<Stmt> },
 
     /// A code block
     Block(Vec<Stmt>),
 
+    /// A for loop, running `code` once for every item of `iterable`.
+    /// 
+    /// `names` holds either one name, bound to each item (or key for maps),
+    /// or two names, bound to the index (or key for maps) and item.
+    For { names: Vec<String>, iterable: Box<Expr>, code: Vec<Stmt> },
+
+    // A statement that exits the innermost loop
+    Break,
+    // A statement that skips to the next iteration of the innermost loop
+    Continue,
+    // A function declaration
+    Fn(FnStmt),
+    // A statement that exits the current function, with an optional returned value
+    Return(Option<Box<Expr>>),
     // A statement that runs bash code
     Bash(String),
     // A print statement
     Print(PrintStmt),
-    // An assignment
+    // A declaration of a variable in the current scope
+    Let(AssignStmt),
+    // A declaration of a constant in the current scope
+    Const(AssignStmt),
+    // An assignment to an already declared variable
     Assign(AssignStmt),
+    // An assignment to an item of a list or a key of a map
+    IndexAssign(IndexAssignStmt),
     // An expression
     Expr(Box<Expr>),
+    // A statement with a syntax error, which was skipped
+    Error,
 }
 
 /// A print statement.
@@ -25,15 +79,35 @@ pub struct PrintStmt {
     pub value: Box<Expr>
 }
 
-/// An assignment statement.
+/// An assignment statement, also used by declarations.
 /// 
-/// Example: `let x = 23;`
+/// Example: `x = 23;`
 #[derive(Clone, Debug, PartialEq)]
 pub struct AssignStmt {
     pub name: String,
     pub value: Box<Expr>
 }
 
+/// An assignment to an item of a list or a key of a map.
+/// 
+/// Example: `xs[0] = 5;`
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexAssignStmt {
+    pub target: Box<Expr>,
+    pub index: Box<Expr>,
+    pub value: Box<Expr>
+}
+
+/// A function declaration statement.
+/// 
+/// Example: `fn add(a, b) { return a + b; }`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FnStmt {
+    pub name: String,
+    pub params: Vec<String>,
+    pub code: Vec<Stmt>
+}
+
 /// An operator (eg. '+', '-').
 #[derive(Clone, Debug, PartialEq)]
 pub enum Op {
@@ -54,9 +128,37 @@ pub enum CompareOp {
     LessEqual,
 }
 
-/// An expression.
+/// A logical operator (eg. 'and', 'or').
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Or
+}
+
+/// An expression, along with the span of source code it was parsed from.
+#[derive(Clone, Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span
+}
+
+impl Expr {
+    /// Constructs a new boxed expression of `kind` spanning `span`.
+    pub fn boxed(kind: ExprKind, span: Span) -> Box<Self> {
+        Box::new(Self { kind, span })
+    }
+}
+
+// Spans are ignored, so that the same code parsed from different places is equal
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+/// A kind of expression.
 #[derive(Clone, Debug, PartialEq)]
-pub enum Expr {
+pub enum ExprKind {
     // A read expression, with an optional prepended message.
     Read,
 
@@ -70,13 +172,51 @@ pub enum Expr {
         rhs: Box<Expr>
     },
 
+    /// A short-circuiting logical expression.
+    Logical {
+        lhs: Box<Expr>,
+        op: LogicalOp,
+        rhs: Box<Expr>
+    },
+    /// A logical not expression.
+    Not(Box<Expr>),
+
     // Number expressions
     Binary(BinaryExpr),
+    Neg(Box<Expr>),
     Num(f64),
 
     Str(String),
+    /// A string with interpolated expressions.
+    Interp(Vec<InterpPart>),
+    /// Shell code that is run, with interpolated expressions that are quoted for the shell.
+    Shell(Vec<InterpPart>),
     // An identifier
-    Ident(String)
+    Ident(String),
+
+    /// A function call.
+    Call(CallExpr),
+
+    /// A list literal.
+    List(Vec<Box<Expr>>),
+    /// A map literal, holding keys and values in order.
+    Map(Vec<(Box<Expr>, Box<Expr>)>),
+    /// An index into a list counting from the end if negative, or a key of a map.
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>
+    },
+    /// A range of whole numbers, from `start` up to but not including `end`.
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>
+    },
+    /// A slice of a list, from `start` up to but not including `end`.
+    Slice {
+        target: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -86,52 +226,361 @@ pub struct BinaryExpr {
     pub rhs: Box<Expr>
 }
 
-/// Returns code with comments (lines starting with '//') processed out.
-pub fn filter_comments(code: &str) -> String {
-    let mut lines = Vec::new();
+/// A part of an interpolated string.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InterpPart {
+    /// Plain text.
+    Str(String),
+    /// An expression whose value is inserted into the string.
+    Expr(Box<Expr>)
+}
 
-    // Skips every line starting with "//"
-    for line in code.lines() {
-        if !line.starts_with("//") {
-            lines.push(line)
+/// A function call expression.
+/// 
+/// Example: `add(1, 2)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallExpr {
+    pub name: String,
+    pub args: Vec<Box<Expr>>
+}
+
+/// Parses a program from `code`, recovering from syntax errors to return all of them at once.
+///
+/// The returned ast and diagnostics own everything they hold, so `code` can be dropped right after.
+pub fn parse_program(code: &str) -> Result<Vec<Stmt>, Diagnostics> {
+    let (ast, diagnostics) = parse_partial_program(code);
+
+    if diagnostics.0.is_empty() {
+        Ok(ast)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Parses a program from `code`, returning the ast recovered around its syntax errors along with them.
+///
+/// Statements with errors are kept as `StmtKind::Error` nodes, and an error that can't be recovered from, like an
+/// unterminated string, ends the ast with one spanning the rest of `code`.
+pub fn parse_partial_program(code: &str) -> (Vec<Stmt>, Diagnostics) {
+    let mut errors = Vec::new();
+    let result = grammar::ProgramParser::new().parse(&mut errors, Lexer::new(code));
+
+    let (mut ast, mut diagnostics) = match result {
+        Ok(ast) => (ast, Diagnostics(errors)),
+        Err(e) => {
+            // Keep errors recovered from before the one that couldn't be
+            let diagnostic: Diagnostic = e.into();
+            let start = diagnostic.span.start;
+            errors.push(diagnostic);
+
+            // Keep the statements before it too, which are only parsed again for their ast
+            let mut ast = grammar::ProgramParser::new()
+                .parse(&mut Vec::new(), Lexer::new(&code[..start]))
+                .unwrap_or_default();
+            match ast.last_mut() {
+                Some(Stmt { kind: StmtKind::Error, span }) => span.end = code.len(),
+                _ => ast.push(Stmt::new(StmtKind::Error, Span::new(start, code.len())))
+            }
+
+            (ast, Diagnostics(errors))
+        }
+    };
+
+    // Statements nested too deeply are errors too
+    for stmt in &mut ast {
+        if let Some(span) = find_too_deep_stmt(stmt, MAX_NESTING_DEPTH) {
+            // Only point at where the nesting goes too deep, since the rest of the span can be long
+            diagnostics.0.push(Diagnostic::new("code is nested too deeply", Span::new(span.start, span.start))
+                .with_help(format!("statements and expressions can be nested at most {MAX_NESTING_DEPTH} deep")));
+            *stmt = Stmt::new(StmtKind::Error, stmt.span);
         }
     }
 
-    lines.join("\n")
+    (ast, diagnostics)
+}
+
+/// Returns the span of the first statement or expression in `stmt` nested more than `depth_left` deep, if any.
+fn find_too_deep_stmt(stmt: &Stmt, depth_left: usize) -> Option<Span> {
+    let Some(depth_left) = depth_left.checked_sub(1) else {
+        return Some(stmt.span);
+    };
+
+    let (exprs, blocks): (Vec<&Expr>, Vec<&Vec<Stmt>>) = match &stmt.kind {
+        StmtKind::If { comparison, code, else_code } => (vec![comparison], [Some(code), else_code.as_ref()].into_iter().flatten().collect()),
+        StmtKind::While { comparison, code } => (vec![comparison], vec![code]),
+        StmtKind::For { iterable, code, .. } => (vec![iterable], vec![code]),
+        StmtKind::Block(code) | StmtKind::Fn(FnStmt { code, .. }) => (vec![], vec![code]),
+        StmtKind::Return(value) => (value.iter().map(|e| &**e).collect(), vec![]),
+        StmtKind::Print(PrintStmt { value }) | StmtKind::Expr(value) => (vec![value], vec![]),
+        StmtKind::Let(a) | StmtKind::Const(a) | StmtKind::Assign(a) => (vec![&a.value], vec![]),
+        StmtKind::IndexAssign(a) => (vec![&a.target, &a.index, &a.value], vec![]),
+        StmtKind::Break | StmtKind::Continue | StmtKind::Bash(_) | StmtKind::Error => (vec![], vec![])
+    };
+
+    exprs.into_iter().find_map(|e| find_too_deep_expr(e, depth_left))
+        .or_else(|| blocks.into_iter().flatten().find_map(|s| find_too_deep_stmt(s, depth_left)))
+}
+
+/// Returns the span of the first expression in `expr` nested more than `depth_left` deep, if any.
+fn find_too_deep_expr(expr: &Expr, depth_left: usize) -> Option<Span> {
+    let Some(depth_left) = depth_left.checked_sub(1) else {
+        return Some(expr.span);
+    };
+
+    let children: Vec<&Expr> = match &expr.kind {
+        ExprKind::Comparison { lhs, rhs, .. }
+        | ExprKind::Logical { lhs, rhs, .. }
+        | ExprKind::Binary(BinaryExpr { lhs, rhs, .. })
+        | ExprKind::Index { target: lhs, index: rhs }
+        | ExprKind::Range { start: lhs, end: rhs } => vec![lhs, rhs],
+        ExprKind::Not(e) | ExprKind::Neg(e) => vec![e],
+        ExprKind::Interp(parts) | ExprKind::Shell(parts) => parts.iter().filter_map(|p| match p {
+            InterpPart::Expr(e) => Some(&**e),
+            InterpPart::Str(_) => None
+        }).collect(),
+        ExprKind::Call(CallExpr { args: items, .. }) | ExprKind::List(items) => items.iter().map(|e| &**e).collect(),
+        ExprKind::Map(pairs) => pairs.iter().flat_map(|(k, v)| [&**k, &**v]).collect(),
+        ExprKind::Slice { target, start, end } => [Some(target), start.as_ref(), end.as_ref()].into_iter().flatten().map(|e| &**e).collect(),
+        ExprKind::Read | ExprKind::Bool(_) | ExprKind::Num(_) | ExprKind::Str(_) | ExprKind::Ident(_) => vec![]
+    };
+
+    children.into_iter().find_map(|e| find_too_deep_expr(e, depth_left))
 }
 
 #[cfg(test)]
 mod tests {
     // Use stuff
     use super::*;
-    use crate::grammar;
+
+    /// Returns an expression of `kind` with an empty span, since spans are ignored when comparing.
+    fn expr(kind: ExprKind) -> Expr {
+        Expr { kind, span: Span::default() }
+    }
+
+    /// Returns a statement of `kind` with an empty span, since spans are ignored when comparing.
+    fn stmt(kind: StmtKind) -> Stmt {
+        Stmt::new(kind, Span::default())
+    }
 
     #[test]
     fn test_parser_if() {
         let code = "if 12 < 23 { print 23; }";
         let parser = grammar::StmtParser::new();
 
-        assert_eq!(parser.parse(code).unwrap(), Stmt::If {
-            comparison: Box::new(Expr::Comparison {
-                lhs: Box::new(Expr::Num(12.0)),
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), stmt(StmtKind::If {
+            comparison: Box::new(expr(ExprKind::Comparison {
+                lhs: Box::new(expr(ExprKind::Num(12.0))),
                 op: CompareOp::Less,
-                rhs: Box::new(Expr::Num(23.0))
-            }),
-            code: vec![Stmt::Print(PrintStmt { value: Box::new(Expr::Num(23.0)) })]
-        });
+                rhs: Box::new(expr(ExprKind::Num(23.0)))
+            })),
+            code: vec![stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Num(23.0))) }))],
+            else_code: None
+        }));
+    }
+
+    #[test]
+    fn test_parser_if_else() {
+        let code = "if 1 > 2 { print 1; } else if 2 > 3 { print 2; } else { print 3; }";
+        let parser = grammar::StmtParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), stmt(StmtKind::If {
+            comparison: Box::new(expr(ExprKind::Comparison {
+                lhs: Box::new(expr(ExprKind::Num(1.0))),
+                op: CompareOp::Greater,
+                rhs: Box::new(expr(ExprKind::Num(2.0)))
+            })),
+            code: vec![stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Num(1.0))) }))],
+            else_code: Some(vec![stmt(StmtKind::If {
+                comparison: Box::new(expr(ExprKind::Comparison {
+                    lhs: Box::new(expr(ExprKind::Num(2.0))),
+                    op: CompareOp::Greater,
+                    rhs: Box::new(expr(ExprKind::Num(3.0)))
+                })),
+                code: vec![stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Num(2.0))) }))],
+                else_code: Some(vec![stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Num(3.0))) }))])
+            })])
+        }));
     }
     
+    #[test]
+    fn test_parser_while() {
+        let code = "while 12 < 23 { break; continue; }";
+        let parser = grammar::StmtParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), stmt(StmtKind::While {
+            comparison: Box::new(expr(ExprKind::Comparison {
+                lhs: Box::new(expr(ExprKind::Num(12.0))),
+                op: CompareOp::Less,
+                rhs: Box::new(expr(ExprKind::Num(23.0)))
+            })),
+            code: vec![stmt(StmtKind::Break), stmt(StmtKind::Continue)]
+        }));
+    }
+
+    #[test]
+    fn test_parser_fn() {
+        let code = "fn add(a, b) { return a + b; }";
+        let parser = grammar::StmtParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), stmt(StmtKind::Fn(FnStmt {
+            name: "add".to_string(),
+            params: vec!["a".to_string(), "b".to_string()],
+            code: vec![stmt(StmtKind::Return(Some(Box::new(expr(ExprKind::Binary(BinaryExpr {
+                lhs: Box::new(expr(ExprKind::Ident("a".to_string()))),
+                op: Op::Plus,
+                rhs: Box::new(expr(ExprKind::Ident("b".to_string())))
+            }))))))]
+        })));
+    }
+
+    #[test]
+    fn test_parser_call() {
+        let code = "add(1, x) * 2";
+        let parser = grammar::ExprParser::new();
+
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), expr(ExprKind::Binary(BinaryExpr {
+            lhs: Box::new(expr(ExprKind::Call(CallExpr {
+                name: "add".to_string(),
+                args: vec![Box::new(expr(ExprKind::Num(1.0))), Box::new(expr(ExprKind::Ident("x".to_string())))]
+            }))),
+            op: Op::Mult,
+            rhs: Box::new(expr(ExprKind::Num(2.0)))
+        })));
+    }
+
+    #[test]
+    fn test_parser_program() {
+        // Only the last statement of a program may omit its delimiter
+        let code = "f(1); x = f(2); x";
+        let parser = grammar::ProgramParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap().len(), 3);
+        assert!(parse_program("x y").is_err());
+    }
+
+    #[test]
+    fn test_parser_error_recovery() {
+        let code = "let x = 1 2;\nprint x;\nif x { print 3 4 }\nlet = 5;";
+        let mut errors = Vec::new();
+        let ast = grammar::ProgramParser::new().parse(&mut errors, Lexer::new(code)).unwrap();
+
+        // Statements with errors are skipped up to the next delimiter or the end of their block
+        assert_eq!(ast.len(), 4);
+        assert_eq!(ast[0], stmt(StmtKind::Error));
+        assert_eq!(ast[1], stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Ident("x".to_string()))) })));
+        assert!(matches!(&ast[2].kind, StmtKind::If { code, .. } if code == &vec![stmt(StmtKind::Error)]));
+        assert_eq!(ast[3], stmt(StmtKind::Error));
+
+        let spans: Vec<_> = errors.iter().map(|e| e.span).collect();
+        assert_eq!(spans, vec![Span::new(10, 11), Span::new(37, 38), Span::new(45, 46)]);
+
+        // An error at the end of input is recovered from too
+        let errors = parse_program("let x = 1 2; if x {").unwrap_err();
+        assert_eq!(errors.0.len(), 2);
+    }
+
+    #[test]
+    fn test_parser_partial_program() {
+        // Every statement is kept, with errors in place of the ones that couldn't be parsed
+        let (ast, errors) = parse_partial_program("print 1;\nlet = 2;\nprint 3;\nprint 4 5;\nprint 6;");
+        let kinds: Vec<_> = ast.iter().map(|s| matches!(s.kind, StmtKind::Error)).collect();
+        assert_eq!(kinds, vec![false, true, false, true, false]);
+        assert_eq!(errors.0.len(), 2);
+
+        // An error at the end of input ends the ast
+        let (ast, errors) = parse_partial_program("print 1; if x {");
+        assert_eq!(ast.len(), 2);
+        assert_eq!(ast[0], stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Num(1.0))) })));
+        assert_eq!(ast[1], stmt(StmtKind::Error));
+        assert_eq!(ast[1].span.end, 15);
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].span, Span::new(15, 15));
+
+        // Statements before an error that can't be recovered from are kept, and the rest of the code is an error
+        let (ast, errors) = parse_partial_program("let x = 1 2;\nprint x;\nprint \"a\nprint 2;");
+        assert_eq!(ast.len(), 3);
+        assert_eq!(ast[0], stmt(StmtKind::Error));
+        assert_eq!(ast[1], stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Ident("x".to_string()))) })));
+        assert_eq!(ast[2].span, Span::new(22, 39));
+        assert_eq!(errors.0.len(), 2);
+
+        // Code without errors is parsed the same as by `parse_program`
+        let (ast, errors) = parse_partial_program("print 1;");
+        assert_eq!(Ok(ast), parse_program("print 1;"));
+        assert!(errors.0.is_empty());
+    }
+
+    #[test]
+    fn test_parser_nesting_depth() {
+        let sum = |terms: usize| vec!["1"; terms].join(" + ");
+        assert!(parse_program(&format!("print {};", sum(200))).is_ok());
+
+        // Only the statement nested too deeply is an error
+        let code = format!("print 1;\nprint {};\nprint 3;", sum(2000));
+        let (ast, errors) = parse_partial_program(&code);
+        assert_eq!(ast.len(), 3);
+        assert_eq!(ast[1], stmt(StmtKind::Error));
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].message, "code is nested too deeply");
+        assert_eq!(errors.0[0].span, Span::new(15, 15));
+
+        // Blocks, brackets and calls count too
+        let nested = |open: &str, inner: &str, close: &str| format!("{}{inner}{}", open.repeat(2000), close.repeat(2000));
+        assert!(parse_program(&nested("{ ", "print 1;", " }")).is_err());
+        assert!(parse_program(&nested("print ", "1;", "")).is_err());
+        assert!(parse_program(&format!("print {};", nested("[", "1", "]"))).is_err());
+        assert!(parse_program(&format!("print {};", nested("f(", "1", ")"))).is_err());
+        assert!(parse_program(&format!("print {};", nested("(", "1", ")"))).is_ok());
+    }
+
+    #[test]
+    fn test_parser_outlives_code() {
+        // Neither the ast nor the diagnostics borrow from the code they came from
+        let parse = |code: String| parse_program(&code);
+
+        let ast = parse("let x = \"hi {name}\";".to_string()).unwrap();
+        assert!(matches!(&ast[0].kind, StmtKind::Let(a) if a.name == "x"));
+
+        let errors = parse("let = 1;".to_string()).unwrap_err();
+        assert_eq!(errors.0[0].span, Span::new(4, 5));
+    }
+
+    #[test]
+    fn test_parser_for() {
+        let code = "for i, x in 0..n + 1 { continue; }";
+        let parser = grammar::StmtParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), stmt(StmtKind::For {
+            names: vec!["i".to_string(), "x".to_string()],
+            iterable: Box::new(expr(ExprKind::Range {
+                start: Box::new(expr(ExprKind::Num(0.0))),
+                end: Box::new(expr(ExprKind::Binary(BinaryExpr {
+                    lhs: Box::new(expr(ExprKind::Ident("n".to_string()))),
+                    op: Op::Plus,
+                    rhs: Box::new(expr(ExprKind::Num(1.0)))
+                })))
+            })),
+            code: vec![stmt(StmtKind::Continue)]
+        }));
+
+        // Decimal numbers still parse next to ranges
+        assert_eq!(
+            *grammar::ExprParser::new().parse(&mut Vec::new(), Lexer::new("1.5")).unwrap(),
+            expr(ExprKind::Num(1.5))
+        );
+    }
+
     #[test]
     fn test_parser_bool() {
         let parser = grammar::ExprParser::new();
 
         // Test true boolean
         let mut code = "true";
-        assert_eq!(parser.parse(code).unwrap(), Box::new(Expr::Bool(true)));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), Box::new(expr(ExprKind::Bool(true))));
 
         // Test false boolean
         code = "false";
-        assert_eq!(parser.parse(code).unwrap(), Box::new(Expr::Bool(false)));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), Box::new(expr(ExprKind::Bool(false))));
     }
 
     #[test]
@@ -139,9 +588,9 @@ mod tests {
         let code = "{ print 23; }";
         let parser = grammar::StmtParser::new();
 
-        assert_eq!(parser.parse(code).unwrap(), Stmt::Block(
-            vec![Stmt::Print(PrintStmt { value: Box::new(Expr::Num(23.0)) })]
-        ));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), stmt(StmtKind::Block(
+            vec![stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Num(23.0))) }))]
+        )));
     }
 
     #[test]
@@ -149,25 +598,110 @@ mod tests {
         let code = "2 + 2 > 3 * 2";
         let parser = grammar::ComparisonParser::new();
 
-        if let Expr::Comparison { .. } = *parser.parse(code).unwrap() {
+        if let ExprKind::Comparison { .. } = parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap().kind {
 
         } else {
             panic!("Comparison should result from ComparisonParser!");
         }
 
-        assert_eq!(*parser.parse(code).unwrap(), Expr::Comparison {
-            lhs: Box::new(Expr::Binary(BinaryExpr {
-                lhs: Box::new(Expr::Num(2.0)),
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), expr(ExprKind::Comparison {
+            lhs: Box::new(expr(ExprKind::Binary(BinaryExpr {
+                lhs: Box::new(expr(ExprKind::Num(2.0))),
                 op: Op::Plus,
-                rhs: Box::new(Expr::Num(2.0))
-            })),
+                rhs: Box::new(expr(ExprKind::Num(2.0)))
+            }))),
             op: CompareOp::Greater,
-            rhs: Box::new(Expr::Binary(BinaryExpr {
-                lhs: Box::new(Expr::Num(3.0)),
+            rhs: Box::new(expr(ExprKind::Binary(BinaryExpr {
+                lhs: Box::new(expr(ExprKind::Num(3.0))),
                 op: Op::Mult,
-                rhs: Box::new(Expr::Num(2.0))
+                rhs: Box::new(expr(ExprKind::Num(2.0)))
+            })))
+        }));
+    }
+
+    #[test]
+    fn test_parser_logical() {
+        let code = "not x == 1 and y or z";
+        let parser = grammar::ExprParser::new();
+
+        // Binds as `((not (x == 1)) and y) or z`
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(), expr(ExprKind::Logical {
+            lhs: Box::new(expr(ExprKind::Logical {
+                lhs: Box::new(expr(ExprKind::Not(Box::new(expr(ExprKind::Comparison {
+                    lhs: Box::new(expr(ExprKind::Ident("x".to_string()))),
+                    op: CompareOp::Equal,
+                    rhs: Box::new(expr(ExprKind::Num(1.0)))
+                }))))),
+                op: LogicalOp::And,
+                rhs: Box::new(expr(ExprKind::Ident("y".to_string())))
+            })),
+            op: LogicalOp::Or,
+            rhs: Box::new(expr(ExprKind::Ident("z".to_string())))
+        }));
+    }
+
+    #[test]
+    fn test_parser_list() {
+        let parser = grammar::ExprParser::new();
+        let xs = || Box::new(expr(ExprKind::Ident("xs".to_string())));
+
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new("[1, 2,]")).unwrap(), expr(ExprKind::List(vec![
+            Box::new(expr(ExprKind::Num(1.0))),
+            Box::new(expr(ExprKind::Num(2.0)))
+        ])));
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new("xs[0] * 2")).unwrap(), expr(ExprKind::Binary(BinaryExpr {
+            lhs: Box::new(expr(ExprKind::Index { target: xs(), index: Box::new(expr(ExprKind::Num(0.0))) })),
+            op: Op::Mult,
+            rhs: Box::new(expr(ExprKind::Num(2.0)))
+        })));
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new("xs[1:]")).unwrap(), expr(ExprKind::Slice {
+            target: xs(),
+            start: Some(Box::new(expr(ExprKind::Num(1.0)))),
+            end: None
+        }));
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new("xs[-1]")).unwrap(), expr(ExprKind::Index {
+            target: xs(),
+            index: Box::new(expr(ExprKind::Neg(Box::new(expr(ExprKind::Num(1.0))))))
+        }));
+    }
+
+    #[test]
+    fn test_parser_map() {
+        let parser = grammar::ExprParser::new();
+
+        assert_eq!(*parser.parse(&mut Vec::new(), Lexer::new(r#"{"n": 3, "s": {}}.n"#)).unwrap(), expr(ExprKind::Index {
+            target: Box::new(expr(ExprKind::Map(vec![
+                (Box::new(expr(ExprKind::Str("n".to_string()))), Box::new(expr(ExprKind::Num(3.0)))),
+                (Box::new(expr(ExprKind::Str("s".to_string()))), Box::new(expr(ExprKind::Map(vec![]))))
+            ]))),
+            index: Box::new(expr(ExprKind::Str("n".to_string())))
+        }));
+
+        // A statement starting with a brace is always a code block
+        assert_eq!(
+            grammar::StmtParser::new().parse(&mut Vec::new(), Lexer::new("{ }")).unwrap(),
+            stmt(StmtKind::Block(vec![]))
+        );
+        assert_eq!(
+            grammar::StmtParser::new().parse(&mut Vec::new(), Lexer::new("m.n = 3;")).unwrap(),
+            stmt(StmtKind::IndexAssign(IndexAssignStmt {
+                target: Box::new(expr(ExprKind::Ident("m".to_string()))),
+                index: Box::new(expr(ExprKind::Str("n".to_string()))),
+                value: Box::new(expr(ExprKind::Num(3.0)))
             }))
-        });
+        );
+    }
+
+    #[test]
+    fn test_parser_index_assign() {
+        let code = "xs[0] = 5;";
+        let parser = grammar::StmtParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(stmt(StmtKind::IndexAssign(IndexAssignStmt {
+            target: Box::new(expr(ExprKind::Ident("xs".to_string()))),
+            index: Box::new(expr(ExprKind::Num(0.0))),
+            value: Box::new(expr(ExprKind::Num(5.0)))
+        }))));
     }
 
     #[test]
@@ -176,8 +710,14 @@ mod tests {
         let parser = grammar::StmtParser::new();
 
         assert_eq!(
-            parser.parse(code).unwrap(),
-            Stmt::Bash("echo Hello".to_string())
+            parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(),
+            stmt(StmtKind::Bash("echo Hello".to_string()))
+        );
+
+        // Bash code isn't interpolated, so its braces don't have to be balanced
+        assert_eq!(
+            parser.parse(&mut Vec::new(), Lexer::new(r#"'"echo {"';"#)).unwrap(),
+            stmt(StmtKind::Bash("echo {".to_string()))
         );
     }
 
@@ -187,16 +727,16 @@ mod tests {
         let parser = grammar::StmtParser::new();
 
         assert_eq!(
-            parser.parse(code).unwrap(),
-            Stmt::Print(PrintStmt {
+            parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(),
+            stmt(StmtKind::Print(PrintStmt {
                 value: Box::new(
-                    Expr::Binary(BinaryExpr {
-                        lhs: Box::new(Expr::Num(1.0)),
+                    expr(ExprKind::Binary(BinaryExpr {
+                        lhs: Box::new(expr(ExprKind::Num(1.0))),
                         op: Op::Plus,
-                        rhs: Box::new(Expr::Num(2.0))
-                    })
+                        rhs: Box::new(expr(ExprKind::Num(2.0)))
+                    }))
                 )
-            })
+            }))
          );
     }
 
@@ -206,10 +746,10 @@ mod tests {
         let parser = grammar::StmtParser::new();
 
         assert_eq!(
-            parser.parse(code).unwrap(),
-            Stmt::Print(PrintStmt {
-                value: Box::new(Expr::Str("Hello, world!".to_string()))
-            })
+            parser.parse(&mut Vec::new(), Lexer::new(code)).unwrap(),
+            stmt(StmtKind::Print(PrintStmt {
+                value: Box::new(expr(ExprKind::Str("Hello, world!".to_string())))
+            }))
         );
     }
 
@@ -218,11 +758,29 @@ mod tests {
         let code = "x = 23;";
         let parser = grammar::StmtParser::new();
 
-        assert_eq!(parser.parse(code), Ok(
-            Stmt::Assign(AssignStmt {
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(
+            stmt(StmtKind::Assign(AssignStmt {
                 name: "x".to_string(),
-                value: Box::new(Expr::Num(23.0))
-            })
+                value: Box::new(expr(ExprKind::Num(23.0)))
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_parser_let_const() {
+        let parser = grammar::StmtParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new("let x = 23;")), Ok(
+            stmt(StmtKind::Let(AssignStmt {
+                name: "x".to_string(),
+                value: Box::new(expr(ExprKind::Num(23.0)))
+            }))
+        ));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new("const x = 23;")), Ok(
+            stmt(StmtKind::Const(AssignStmt {
+                name: "x".to_string(),
+                value: Box::new(expr(ExprKind::Num(23.0)))
+            }))
         ));
     }
 
@@ -231,7 +789,7 @@ mod tests {
         let code = "read";
         let parser = grammar::StmtParser::new();
 
-        assert_eq!(parser.parse(code), Ok(Stmt::Expr(Box::new(Expr::Read))));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(stmt(StmtKind::Expr(Box::new(expr(ExprKind::Read))))));
     }
 
     #[test]
@@ -240,17 +798,17 @@ mod tests {
         let parser = grammar::StmtParser::new();
 
         assert_eq!(
-            parser.parse(code),
+            parser.parse(&mut Vec::new(), Lexer::new(code)),
             Ok(
-                Stmt::Expr(
+                stmt(StmtKind::Expr(
                     Box::new(
-                        Expr::Binary(BinaryExpr {
-                            lhs: Box::new(Expr::Num(23.0)),
+                        expr(ExprKind::Binary(BinaryExpr {
+                            lhs: Box::new(expr(ExprKind::Num(23.0))),
                             op: Op::Plus,
-                            rhs: Box::new(Expr::Num(42.0))
-                        })
+                            rhs: Box::new(expr(ExprKind::Num(42.0)))
+                        }))
                     )
-                )
+                ))
             )
         );
     }
@@ -260,7 +818,7 @@ mod tests {
         let code = "x";
         let parser = grammar::IdentParser::new();
 
-        assert_eq!(parser.parse(code), Ok(Box::new(Expr::Ident("x".to_string()))));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(Box::new(expr(ExprKind::Ident("x".to_string())))));
     }
 
     #[test]
@@ -268,7 +826,104 @@ mod tests {
         let code = "23";
         let parser = grammar::StmtParser::new();
 
-        assert_eq!(parser.parse(code), Ok(Stmt::Expr(Box::new(Expr::Num(23.0)))));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(stmt(StmtKind::Expr(Box::new(expr(ExprKind::Num(23.0)))))));
+    }
+
+    #[test]
+    fn test_parser_interp_str() {
+        let code = r#""Hi {name}, {n * 2} {{items}}""#;
+        let parser = grammar::StrParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(Box::new(expr(ExprKind::Interp(vec![
+            InterpPart::Str("Hi ".to_string()),
+            InterpPart::Expr(Box::new(expr(ExprKind::Ident("name".to_string())))),
+            InterpPart::Str(", ".to_string()),
+            InterpPart::Expr(Box::new(expr(ExprKind::Binary(BinaryExpr {
+                lhs: Box::new(expr(ExprKind::Ident("n".to_string()))),
+                op: Op::Mult,
+                rhs: Box::new(expr(ExprKind::Num(2.0)))
+            })))),
+            InterpPart::Str(" {items}".to_string())
+        ])))));
+
+        // Escaped braces alone make a plain string
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(r#""{{}}""#)), Ok(Box::new(expr(ExprKind::Str("{}".to_string())))));
+
+        assert!(parser.parse(&mut Vec::new(), Lexer::new(r#""{""#)).is_err());
+        assert!(parser.parse(&mut Vec::new(), Lexer::new(r#""}""#)).is_err());
+        assert!(parser.parse(&mut Vec::new(), Lexer::new(r#""{}""#)).is_err());
+        assert!(parser.parse(&mut Vec::new(), Lexer::new(r#""{1 +}""#)).is_err());
+
+        // A lone brace is reported as one, rather than as a string that isn't terminated
+        let errors = parse_program(r#"print "{";"#).unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert!(errors.0[0].message.starts_with("unclosed '{' in string"), "{}", errors.0[0].message);
+    }
+
+    #[test]
+    fn test_parser_interp_nested_str() {
+        let code = r#""{m["k"]}""#;
+        let parser = grammar::StrParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(Box::new(expr(ExprKind::Interp(vec![
+            InterpPart::Expr(Box::new(expr(ExprKind::Index {
+                target: Box::new(expr(ExprKind::Ident("m".to_string()))),
+                index: Box::new(expr(ExprKind::Str("k".to_string())))
+            })))
+        ])))));
+    }
+
+    #[test]
+    fn test_parser_spans() {
+        let code = "let x = 1;\nprint x + \"{x}\";";
+        let ast = grammar::ProgramParser::new().parse(&mut Vec::new(), Lexer::new(code)).unwrap();
+
+        assert_eq!(ast[0].span, Span::new(0, 10));
+        assert_eq!(ast[1].span, Span::new(11, 27));
+
+        // Expressions inside of strings are spanned where they are in the source code
+        if let StmtKind::Print(PrintStmt { value }) = &ast[1].kind {
+            assert_eq!(value.span, Span::new(17, 26));
+            if let ExprKind::Binary(BinaryExpr { rhs, .. }) = &value.kind {
+                if let ExprKind::Interp(parts) = &rhs.kind {
+                    assert!(matches!(&parts[0], InterpPart::Expr(e) if e.span == Span::new(23, 24)));
+                    return;
+                }
+            }
+        }
+        panic!("print statement of a sum with a string should result from ProgramParser!");
+    }
+
+    #[test]
+    fn test_parser_comments() {
+        let code = "
+            // A line comment
+            let x1 = 1; // A trailing comment
+            /* A block /* with a nested block */
+               comment */ print x1;
+        ";
+        let parser = grammar::ProgramParser::new();
+
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(vec![
+            stmt(StmtKind::Let(AssignStmt { name: "x1".to_string(), value: Box::new(expr(ExprKind::Num(1.0))) })),
+            stmt(StmtKind::Print(PrintStmt { value: Box::new(expr(ExprKind::Ident("x1".to_string()))) }))
+        ]));
+    }
+
+    #[test]
+    fn test_parser_str_kinds() {
+        let parser = grammar::StrParser::new();
+        let str_expr = |s: &str| Ok(Box::new(expr(ExprKind::Str(s.to_string()))));
+
+        // Escape sequences are replaced
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(r#""a\"b\"\n\u0041""#)), str_expr("a\"b\"\nA"));
+
+        // Raw strings have no escape sequences or placeholders
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(r#"r"C:\path\{x}""#)), str_expr("C:\\path\\{x}"));
+
+        // Triple-quoted strings can span lines, and have their indentation stripped
+        let code = "\"\"\"\n    Hello\n      \"world\"\n    \"\"\"";
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), str_expr("Hello\n  \"world\""));
     }
 
     #[test]
@@ -276,6 +931,6 @@ mod tests {
         let code = r#""Hello, world!""#;
         let parser = grammar::StrParser::new();
 
-        assert_eq!(parser.parse(code), Ok(Box::new(Expr::Str("Hello, world!".to_string()))));
+        assert_eq!(parser.parse(&mut Vec::new(), Lexer::new(code)), Ok(Box::new(expr(ExprKind::Str("Hello, world!".to_string())))));
     }
 }
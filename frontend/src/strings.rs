@@ -0,0 +1,255 @@
+/*!
+    Contains things related to processing string literals.
+*/
+
+use crate::{grammar, lexer::Lexer, parser::{ExprKind, InterpPart}};
+
+/// Replaces escape sequences (eg. `\n`, `\"`, `\u00e9`) in the contents of a string literal with the characters they stand for.
+pub fn unescape_str(contents: &str) -> Result<String, &'static str> {
+    let mut s = String::new();
+    let mut chars = contents.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+
+        // Replace escape sequence
+        s.push(match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                if hex.len() != 4 {
+                    return Err("'\\u' must be followed by 4 hex digits in string");
+                }
+
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or("'\\u' must be followed by 4 hex digits of a valid character in string")?
+            }
+            _ => return Err("invalid escape sequence in string")
+        });
+    }
+
+    Ok(s)
+}
+
+/// Strips the indentation shared by every line of the contents of a triple-quoted string.
+///
+/// A line break right after the opening quotes, and a last line holding only the indentation
+/// of the closing quotes, are dropped too.
+pub fn dedent_str(contents: &str) -> String {
+    dedent_lines(contents)
+        .iter()
+        .map(|(_, l)| *l)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the lines of the contents of a triple-quoted string with their shared indentation stripped,
+/// along with the byte position each stripped line starts at in the contents.
+fn dedent_lines(contents: &str) -> Vec<(usize, &str)> {
+    let first = if contents.starts_with("\r\n") {
+        2
+    } else if contents.starts_with('\n') {
+        1
+    } else {
+        0
+    };
+
+    let mut lines = Vec::new();
+    let mut pos = first;
+    for line in contents[first..].split('\n') {
+        lines.push((pos, line.strip_suffix('\r').unwrap_or(line)));
+        pos += line.len() + 1;
+    }
+
+    // Drop the line holding the closing quotes if they are on their own line
+    if lines.len() > 1 && lines.last().is_some_and(|(_, l)| l.trim().is_empty()) {
+        lines.pop();
+    }
+
+    // Find indentation shared by every line that isn't blank
+    let indent = lines.iter()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(_, l)| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    lines.into_iter()
+        .map(|(pos, l)| match l.get(indent..) {
+            Some(rest) => (pos + indent, rest),
+            None => (pos + l.len() - l.trim_start().len(), l.trim_start())
+        })
+        .collect()
+}
+
+/// Parses the contents of a triple-quoted string, which start at byte position `start` in the source code.
+pub fn parse_triple_quoted_str(contents: &str, start: usize) -> Result<ExprKind, &'static str> {
+    let lines = dedent_lines(contents);
+    let dedented = lines.iter().map(|(_, l)| *l).collect::<Vec<_>>().join("\n");
+
+    // Find where each position of the dedented contents came from in the source code
+    parse_interpolated_str(&dedented, &|pos| {
+        let mut line_start = 0;
+        for (line_pos, line) in &lines {
+            if pos <= line_start + line.len() {
+                return start + line_pos + (pos - line_start);
+            }
+            line_start += line.len() + 1;
+        }
+
+        start + contents.len()
+    })
+}
+
+/// Parses the contents of a string literal, splitting out `{expr}` placeholders and replacing escape sequences.
+///
+/// A plain string is returned if there are no placeholders.
+/// `source_pos` maps a byte position in `contents` to its byte position in the source code, for the spans of placeholders.
+pub fn parse_interpolated_str(contents: &str, source_pos: &dyn Fn(usize) -> usize) -> Result<ExprKind, &'static str> {
+    let mut parts = parse_placeholders(contents, source_pos, unescape_str)?;
+
+    // Strings without placeholders are plain strings
+    match parts.as_mut_slice() {
+        [] => Ok(ExprKind::Str(String::new())),
+        [InterpPart::Str(s)] => Ok(ExprKind::Str(std::mem::take(s))),
+        _ => Ok(ExprKind::Interp(parts))
+    }
+}
+
+/// Parses shell code in backticks, splitting out `{expr}` placeholders.
+///
+/// The text around placeholders is kept as is, so backslashes are left for the shell.
+pub fn parse_shell_str(contents: &str, source_pos: &dyn Fn(usize) -> usize) -> Result<ExprKind, &'static str> {
+    Ok(ExprKind::Shell(parse_placeholders(contents, source_pos, |text| Ok(text.to_string()))?))
+}
+
+/// Splits `contents` into text and `{expr}` placeholders, processing text with `process_text`.
+///
+/// `{{` and `}}` are escapes for literal braces.
+fn parse_placeholders(
+    contents: &str,
+    source_pos: &dyn Fn(usize) -> usize,
+    process_text: fn(&str) -> Result<String, &'static str>
+) -> Result<Vec<InterpPart>, &'static str> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut rest = contents;
+    let mut pos = 0;
+
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '{' if rest.starts_with("{{") => {
+                text.push('{');
+                rest = &rest[2..];
+                pos += 2;
+            }
+            '}' if rest.starts_with("}}") => {
+                text.push('}');
+                rest = &rest[2..];
+                pos += 2;
+            }
+            '{' => {
+                // Find the brace closing this placeholder, skipping nested braces and strings
+                let mut depth = 0;
+                let mut in_str = false;
+                let mut escaped = false;
+                let end = rest.char_indices().find(|&(_, c)| {
+                    if in_str {
+                        match c {
+                            _ if escaped => escaped = false,
+                            '\\' => escaped = true,
+                            '"' => in_str = false,
+                            _ => {}
+                        }
+                        return false;
+                    }
+
+                    match c {
+                        '"' => in_str = true,
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                }).map(|(i, _)| i).ok_or("unclosed '{' in string, use '{{' for a literal '{'")?;
+
+                // Parse placeholder as an expression
+                let source = &rest[1..end];
+                if source.trim().is_empty() {
+                    return Err("empty '{}' in string, use '{{}}' for literal braces");
+                }
+                let expr = grammar::ExprParser::new()
+                    .parse(&mut Vec::new(), Lexer::with_offset(source, source_pos(pos + 1)))
+                    .map_err(|_| "invalid expression inside of '{}' in string")?;
+
+                if !text.is_empty() {
+                    parts.push(InterpPart::Str(process_text(&std::mem::take(&mut text))?));
+                }
+                parts.push(InterpPart::Expr(expr));
+                rest = &rest[end + 1..];
+                pos += end + 1;
+            }
+            '}' => return Err("unmatched '}' in string, use '}}' for a literal '}'"),
+            _ => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+                pos += c.len_utf8();
+            }
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(InterpPart::Str(process_text(&text)?));
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    // Use stuff
+    use super::*;
+
+    #[test]
+    fn test_unescape_str() {
+        assert_eq!(unescape_str(r#"a\tb\n\"c\" \\ \u00e9"#), Ok("a\tb\n\"c\" \\ é".to_string()));
+        assert!(unescape_str(r"\q").is_err());
+        assert!(unescape_str(r"\u00").is_err());
+        assert!(unescape_str(r"\").is_err());
+    }
+
+    #[test]
+    fn test_dedent_str() {
+        let contents = "\n    first\n      second\n\n    third\n    ";
+        assert_eq!(dedent_str(contents), "first\n  second\n\nthird");
+
+        // Closing quotes on the last line of text keep it
+        assert_eq!(dedent_str("\n  a\n  b"), "a\nb");
+        assert_eq!(dedent_str("single line"), "single line");
+    }
+
+    #[test]
+    fn test_parse_shell_str() {
+        // Doubled braces are literal, so shell syntax using braces can be written
+        let parts = match parse_shell_str(r"echo ${{HOME}} \n {dir}", &|pos| pos) {
+            Ok(ExprKind::Shell(parts)) => parts,
+            other => panic!("expected shell code, but got {other:?}")
+        };
+        assert_eq!(parts.len(), 2);
+        assert!(matches!(&parts[0], InterpPart::Str(s) if s == r"echo ${HOME} \n "));
+        assert!(matches!(&parts[1], InterpPart::Expr(e) if e.kind == ExprKind::Ident("dir".to_string())));
+
+        // A single brace always starts a placeholder
+        assert!(parse_shell_str("echo ${HOME", &|pos| pos).is_err());
+    }
+}
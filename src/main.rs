@@ -1,26 +1,79 @@
-use args::parse_args;
-use backend::interpreter;
-use frontend::{grammar, helper::convert_string_to_static_str};
+use std::time::Duration;
+use args::{parse_args, Args, Command};
+use backend::{interpreter::{render_error, Interpreter}, limits::Limits, permissions::Permissions, repl::Repl};
+use frontend::parser::parse_program;
 
 fn main() -> anyhow::Result<()> {
     // Get command line args
     let args = parse_args();
 
-    // Get code from file
-    let code = std::fs::read_to_string(args.file)?;
+    // Get what code is allowed to do from args
+    let permissions = permissions_from_args(&args);
+    let limits = limits_from_args(&args)?;
+
+    // Start a REPL if asked to or if no file was given
+    let file = match (args.command, args.file) {
+        (None, Some(file)) => file,
+        (Some(Command::Repl), _) | (None, None) => return Repl::new().with_permissions(permissions).with_limits(limits).run()
+    };
 
-    // Show ast if option enabled in args
-    if args.show_ast {
-        // Create parser to parse ast
-        let parser = grammar::ProgramParser::new();
+    // Get code from file
+    let code = std::fs::read_to_string(&file)?;
 
+    // Show ast if option enabled in args, otherwise evaluate code
+    let result = if args.show_ast {
         // Print parsed code as ast
-        println!("Ast:\n{:#?}", parser.parse(unsafe { convert_string_to_static_str(code.clone()) })?);
+        parse_program(&code)
+            .map(|ast| println!("Ast:\n{:#?}", ast))
+            .map_err(anyhow::Error::from)
     } else {
-        // Evaluate code
-        _ = interpreter::run_code(&code)?;
+        // Evaluate code with only the permissions and limits given in args
+        parse_program(&code)
+            .map_err(anyhow::Error::from)
+            .and_then(|ast| Interpreter::new(ast).with_permissions(permissions).with_limits(limits).run())
+    };
+
+    // Print errors found in code along with where they were found
+    if let Err(e) = result {
+        eprintln!("{}", render_error(&e, &file, &code));
+        std::process::exit(1);
     }
 
     // Return no errors
     Ok(())
 }
+
+/// Returns the permissions code gets from command line args.
+fn permissions_from_args(args: &Args) -> Permissions {
+    let mut permissions = match args.sandbox {
+        true => Permissions {
+            file_read: args.allow_read,
+            file_write: args.allow_write,
+            env: args.allow_env,
+            stdin: args.allow_stdin,
+            ..Permissions::sandboxed()
+        },
+        false => Permissions::default()
+    };
+
+    if let Some(programs) = &args.allow_exec {
+        permissions = permissions.allow_exec(programs.iter().filter(|p| !p.is_empty()).cloned());
+    }
+
+    permissions
+}
+
+/// Returns the limits code gets from command line args.
+fn limits_from_args(args: &Args) -> anyhow::Result<Limits> {
+    let timeout = args.timeout
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("invalid timeout: {e}"))?;
+
+    Ok(Limits {
+        max_steps: args.max_steps,
+        max_call_depth: args.max_call_depth.or(Limits::default().max_call_depth),
+        timeout,
+        max_collection_len: args.max_collection_len
+    })
+}